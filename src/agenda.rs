@@ -0,0 +1,75 @@
+//! Open tasks with dates, grouped by day.
+
+use crate::dates::{add_days, to_naive};
+use crate::task::Task;
+use crate::workspace::Project;
+use speedate::Date;
+use std::path::PathBuf;
+
+/// A group of the agenda.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Section {
+    /// Everything with a date before today.
+    Overdue,
+    Day(Date),
+}
+
+/// A task in the agenda together with its project file.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub task: Task,
+}
+
+impl Entry {
+    /// How many days ago the task was due, 0 if it isn't overdue.
+    pub fn overdue_days(&self, today: &Date) -> i64 {
+        self.task
+            .date()
+            .map(|d| (to_naive(today) - to_naive(d)).num_days().max(0))
+            .unwrap_or(0)
+    }
+}
+
+/// Groups the open tasks of `projects` dated before today plus `days` days
+/// (today included). Sections are in order and never empty, tasks in a
+/// section are sorted by date and time, then by priority.
+pub fn agenda(projects: Vec<Project>, today: &Date, days: u32) -> Vec<(Section, Vec<Entry>)> {
+    // None when it's past year 9999, then every date is in.
+    let end = add_days(today, days as i64);
+    let mut entries: Vec<Entry> = projects
+        .into_iter()
+        .flat_map(|p| {
+            let path = p.path;
+            p.tasks.into_iter().map(move |task| Entry {
+                path: path.clone(),
+                task,
+            })
+        })
+        .filter(|e| {
+            !e.task.status().is_closed()
+                && e.task
+                    .date()
+                    .is_some_and(|d| end.as_ref().is_none_or(|end| d < end))
+        })
+        .collect();
+    entries.sort_by_key(|e| {
+        let priority = e.task.priority().map(|p| p.level()).unwrap_or(u8::MAX);
+        (e.task.due_at(), priority)
+    });
+
+    let mut sections: Vec<(Section, Vec<Entry>)> = Vec::new();
+    for entry in entries {
+        let date = entry.task.date().expect("Only tasks with dates are left.");
+        let section = if date < today {
+            Section::Overdue
+        } else {
+            Section::Day(date.clone())
+        };
+        match sections.last_mut() {
+            Some((last, group)) if *last == section => group.push(entry),
+            _ => sections.push((section, vec![entry])),
+        }
+    }
+    sections
+}
@@ -0,0 +1,81 @@
+//! The config file, `~/.rtd`.
+//!
+//! Every line is a `KEY=value` pair, lines starting with # are comments.
+//! `RTD_ROOT` is the only key you have to set. Keys starting with `view.`
+//! define saved views, see [`crate::view`].
+
+use crate::error::{Error, Result};
+use crate::id::IdMode;
+use crate::view::{self, View};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+pub const CONFIG_FNAME: &str = ".rtd";
+pub const RTD_ROOT_VAR_NAME: &str = "RTD_ROOT";
+/// `number` (default) or `hash`, see [`IdMode`].
+pub const RTD_IDS_VAR_NAME: &str = "RTD_IDS";
+/// `true` (default) or `false`, whether `rtd archive` moves cancelled tasks.
+pub const RTD_ARCHIVE_CANCELLED_VAR_NAME: &str = "RTD_ARCHIVE_CANCELLED";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub root: PathBuf,
+    pub id_mode: IdMode,
+    pub archive_cancelled: bool,
+    /// In the order they are defined.
+    pub views: Vec<View>,
+}
+
+impl Config {
+    /// Reads the config from the home directory.
+    pub fn load() -> Result<Config> {
+        // I am surprised that to get your home directory, you need a crate.
+        let home = env::home_dir().ok_or(Error::Config("I need a $HOME to operate.".into()))?;
+        let contents = fs::read_to_string(home.join(CONFIG_FNAME)).map_err(|_| {
+            Error::Config(format!("You need to create a config at ~/{CONFIG_FNAME} and add {RTD_ROOT_VAR_NAME}=<rtd_root_dir_absolute_path> there."))
+        })?;
+        Config::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Config> {
+        let mut root = None;
+        let mut id_mode = IdMode::default();
+        let mut archive_cancelled = true;
+        let mut views = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(Error::Config(format!("Can't parse config line '{line}'.")));
+            };
+            let value = value.trim();
+            match key.trim() {
+                RTD_ROOT_VAR_NAME => root = Some(PathBuf::from(value)),
+                RTD_IDS_VAR_NAME => id_mode = value.parse().map_err(Error::Config)?,
+                RTD_ARCHIVE_CANCELLED_VAR_NAME => {
+                    archive_cancelled = value.parse().map_err(|_| {
+                        Error::Config(format!(
+                            "{RTD_ARCHIVE_CANCELLED_VAR_NAME} should be true or false."
+                        ))
+                    })?
+                }
+                key if key.starts_with("view.") => {
+                    view::parse_config_entry(&mut views, key, value)?
+                }
+                // Keys from newer versions of rtd shouldn't break older ones.
+                _ => {}
+            }
+        }
+        let root = root.ok_or(Error::Config(format!(
+            "You need to have {RTD_ROOT_VAR_NAME}=<absolute_path> in the config."
+        )))?;
+        Ok(Config {
+            root,
+            id_mode,
+            archive_cancelled,
+            views,
+        })
+    }
+}
@@ -0,0 +1,395 @@
+//! Dates in rtd are `speedate::Date`s, chrono does the calendar arithmetic.
+//!
+//! A task's date can have a time, `%2024-01-25T14:30`. A task without a time
+//! is due at the start of its day.
+
+use chrono::prelude::*;
+use chrono::Months;
+use speedate::Date;
+
+const TIME_FORMAT: &str = "%H:%M";
+
+pub fn today() -> Date {
+    let today = Local::now().format("%Y-%m-%d");
+    Date::parse_str_rfc3339(&today.to_string()).expect("Can't parse today's date.")
+}
+
+pub fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+pub fn to_naive(date: &Date) -> NaiveDate {
+    NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)
+        .expect("speedate dates are valid.")
+}
+
+/// None for dates before year 1 or after year 9999, which don't fit in a
+/// YYYY-MM-DD date.
+pub fn from_naive(date: NaiveDate) -> Option<Date> {
+    if !(1..=9999).contains(&date.year()) {
+        return None;
+    }
+    Some(Date {
+        year: date.year() as u16,
+        month: date.month() as u8,
+        day: date.day() as u8,
+    })
+}
+
+/// None when the result is out of range, see [`from_naive`].
+pub fn add_days(date: &Date, days: i64) -> Option<Date> {
+    to_naive(date)
+        .checked_add_signed(chrono::Duration::try_days(days)?)
+        .and_then(from_naive)
+}
+
+/// Adds months, the 31st becomes the last day of a shorter month. None when
+/// the result is out of range, see [`from_naive`].
+pub fn add_months(date: &Date, months: u32) -> Option<Date> {
+    to_naive(date)
+        .checked_add_months(Months::new(months))
+        .and_then(from_naive)
+}
+
+pub fn weekday(date: &Date) -> Weekday {
+    to_naive(date).weekday()
+}
+
+/// Parses `HH:MM`.
+pub fn parse_time(s: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(s, TIME_FORMAT).ok()
+}
+
+/// Parses the date of a task without the %, `2024-01-25` or `2024-01-25T14:30`.
+pub fn parse_due(s: &str) -> Option<(Date, Option<NaiveTime>)> {
+    match s.split_once('T') {
+        Some((date, time)) => Some((Date::parse_str_rfc3339(date).ok()?, Some(parse_time(time)?))),
+        None => Some((Date::parse_str_rfc3339(s).ok()?, None)),
+    }
+}
+
+/// The date of a task without the %, see [`parse_due`].
+pub fn format_due(date: &Date, time: Option<NaiveTime>) -> String {
+    match time {
+        Some(time) => format!("{}T{}", date, time.format(TIME_FORMAT)),
+        None => date.to_string(),
+    }
+}
+
+/// The moment a task is due, the start of the day if it has no time.
+pub fn due_at(date: &Date, time: Option<NaiveTime>) -> NaiveDateTime {
+    to_naive(date).and_time(time.unwrap_or(NaiveTime::MIN))
+}
+
+/// Parses a duration like `30m`, `2h`, `3d` or `1w`.
+pub fn parse_duration(s: &str) -> Option<chrono::Duration> {
+    let unit = s.chars().last()?;
+    let n: i64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    match unit {
+        'm' => chrono::Duration::try_minutes(n),
+        'h' => chrono::Duration::try_hours(n),
+        'd' => chrono::Duration::try_days(n),
+        'w' => chrono::Duration::try_weeks(n),
+        _ => None,
+    }
+}
+
+/// Parses a date written the way people say it, relative to `today`:
+///
+/// - `2024-01-25`
+/// - `today`, `tomorrow` (`tmr`), `yesterday`
+/// - `mon`..`sun` or `monday`..`sunday`, the closest such day after today
+/// - `+3d`, `+2w`, `+1m`, `+1y` and `-1d`
+/// - `next-week` (its Monday), `next-month` and `next-year` (their first day)
+/// - `eow`, `eom` and `eoy`, the last day of this week, month or year
+pub fn parse_date(when: &str, today: &Date) -> Option<Date> {
+    if let Ok(date) = Date::parse_str_rfc3339(when) {
+        return Some(date);
+    }
+    // Dates are written as YYYY-MM-DD.
+    parse_relative(&when.to_lowercase(), to_naive(today)).and_then(from_naive)
+}
+
+fn parse_relative(when: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let weekday = today.weekday().num_days_from_monday() as i64;
+    let first_of_month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1)?;
+    match when {
+        "today" => Some(today),
+        "tomorrow" | "tmr" => today.succ_opt(),
+        "yesterday" => today.pred_opt(),
+        "eow" => Some(today + chrono::Duration::days(6 - weekday)),
+        "eom" => Some(first_of_month + Months::new(1) - chrono::Duration::days(1)),
+        "eoy" => NaiveDate::from_ymd_opt(today.year(), 12, 31),
+        "next-week" => Some(today + chrono::Duration::days(7 - weekday)),
+        "next-month" => Some(first_of_month + Months::new(1)),
+        "next-year" => NaiveDate::from_ymd_opt(today.year() + 1, 1, 1),
+        _ if when.starts_with(['+', '-']) => parse_offset(when, today),
+        _ => {
+            let day = parse_weekday(when)?.num_days_from_monday() as i64;
+            let ahead = (7 + day - weekday) % 7;
+            Some(today + chrono::Duration::days(if ahead == 0 { 7 } else { ahead }))
+        }
+    }
+}
+
+/// `+3d`, `-1w` and the like, counted from `today`.
+fn parse_offset(when: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let sign: i64 = if when.starts_with('-') { -1 } else { 1 };
+    let unit = when.chars().last()?;
+    let digits = when.get(1..when.len() - unit.len_utf8())?;
+    // Only digits, `+-3d` and `++3d` are not offsets.
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let n = sign * digits.parse::<i64>().ok()?;
+    let days = match unit {
+        'd' => n,
+        'w' => n.checked_mul(7)?,
+        'm' => return add_signed_months(today, n),
+        'y' => return add_signed_months(today, n.checked_mul(12)?),
+        _ => return None,
+    };
+    today.checked_add_signed(chrono::Duration::try_days(days)?)
+}
+
+fn add_signed_months(date: NaiveDate, months: i64) -> Option<NaiveDate> {
+    let abs = Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+    if months >= 0 {
+        date.checked_add_months(abs)
+    } else {
+        date.checked_sub_months(abs)
+    }
+}
+
+fn parse_weekday(when: &str) -> Option<Weekday> {
+    let days = [
+        ("monday", Weekday::Mon),
+        ("tuesday", Weekday::Tue),
+        ("wednesday", Weekday::Wed),
+        ("thursday", Weekday::Thu),
+        ("friday", Weekday::Fri),
+        ("saturday", Weekday::Sat),
+        ("sunday", Weekday::Sun),
+    ];
+    days.iter()
+        .find(|(name, _)| when.len() >= 3 && name.starts_with(when))
+        .map(|(_, day)| *day)
+}
+
+/// [`parse_date`] with an optional `THH:MM` after the date.
+pub fn parse_date_time(when: &str, today: &Date) -> Option<(Date, Option<NaiveTime>)> {
+    if let Some((date, time)) = when.rsplit_once('T') {
+        if let (Some(date), Some(time)) = (parse_date(date, today), parse_time(time)) {
+            return Some((date, Some(time)));
+        }
+    }
+    Some((parse_date(when, today)?, None))
+}
+
+/// Replaces every `%when` token in `text` that [`parse_date`] understands with
+/// the date it stands for, e.g. `%tomorrow` with `%2024-01-26`. A time can
+/// follow the date: `%tomorrowT14:30`. Start dates, `^when`, are resolved too.
+/// Everything else, whitespace included, is kept as it is.
+pub fn resolve_dates(text: &str, today: &Date) -> String {
+    let mut resolved = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let word_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (word, after) = rest.split_at(word_len);
+        let due = word
+            .strip_prefix('%')
+            .and_then(|w| parse_date_time(w, today));
+        let start = word.strip_prefix('^').and_then(|w| parse_date(w, today));
+        match (due, start) {
+            (Some((date, time)), _) => resolved.push_str(&format!("%{}", format_due(&date, time))),
+            (_, Some(start)) => resolved.push_str(&format!("^{}", start)),
+            _ => resolved.push_str(word),
+        }
+        let space_len = after.len() - after.trim_start().len();
+        resolved.push_str(&after[..space_len]);
+        rest = &after[space_len..];
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Date {
+        Date::parse_str_rfc3339(s).unwrap()
+    }
+
+    /// `when` from `today`, as YYYY-MM-DD.
+    fn parse(when: &str, today: &str) -> Option<String> {
+        parse_date(when, &date(today)).map(|d| d.to_string())
+    }
+
+    fn check(today: &str, cases: &[(&str, Option<&str>)]) {
+        for (when, expected) in cases {
+            assert_eq!(
+                parse(when, today).as_deref(),
+                *expected,
+                "{} from {}",
+                when,
+                today
+            );
+        }
+    }
+
+    #[test]
+    fn words() {
+        // A Thursday.
+        check(
+            "2024-01-25",
+            &[
+                ("2024-03-01", Some("2024-03-01")),
+                ("today", Some("2024-01-25")),
+                ("tomorrow", Some("2024-01-26")),
+                ("tmr", Some("2024-01-26")),
+                ("yesterday", Some("2024-01-24")),
+                ("eow", Some("2024-01-28")),
+                ("eom", Some("2024-01-31")),
+                ("eoy", Some("2024-12-31")),
+                ("next-week", Some("2024-01-29")),
+                ("next-month", Some("2024-02-01")),
+                ("next-year", Some("2025-01-01")),
+                ("Tomorrow", Some("2024-01-26")),
+                ("someday", None),
+                ("", None),
+                ("2024-02-30", None),
+            ],
+        );
+    }
+
+    #[test]
+    fn weekdays_are_after_today() {
+        check(
+            "2024-01-25",
+            &[
+                ("fri", Some("2024-01-26")),
+                ("sunday", Some("2024-01-28")),
+                ("mon", Some("2024-01-29")),
+                ("wed", Some("2024-01-31")),
+                // The same weekday is a week later.
+                ("thu", Some("2024-02-01")),
+                ("thursday", Some("2024-02-01")),
+                ("Tuesday", Some("2024-01-30")),
+                ("TUE", Some("2024-01-30")),
+                ("tues", Some("2024-01-30")),
+                ("mo", None),
+                ("fridays", None),
+            ],
+        );
+    }
+
+    #[test]
+    fn ends_and_starts_of_periods() {
+        check("2024-02-10", &[("eom", Some("2024-02-29"))]);
+        check("2023-02-10", &[("eom", Some("2023-02-28"))]);
+        check(
+            "2024-12-05",
+            &[
+                ("eom", Some("2024-12-31")),
+                ("next-month", Some("2025-01-01")),
+            ],
+        );
+        // A Sunday, the end of its week, and the next one starts tomorrow.
+        check(
+            "2024-01-28",
+            &[
+                ("eow", Some("2024-01-28")),
+                ("next-week", Some("2024-01-29")),
+            ],
+        );
+        // A Monday.
+        check(
+            "2024-01-29",
+            &[
+                ("eow", Some("2024-02-04")),
+                ("next-week", Some("2024-02-05")),
+            ],
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        check(
+            "2024-01-31",
+            &[
+                ("+3d", Some("2024-02-03")),
+                ("-1d", Some("2024-01-30")),
+                ("+2w", Some("2024-02-14")),
+                ("-1w", Some("2024-01-24")),
+                // The 31st becomes the last day of a shorter month.
+                ("+1m", Some("2024-02-29")),
+                ("+1y", Some("2025-01-31")),
+                ("+0d", Some("2024-01-31")),
+                ("+d", None),
+                ("+3x", None),
+                ("+-3d", None),
+                ("++3d", None),
+                ("+ 3d", None),
+                ("+9999y", None),
+                ("+99999999999999999999d", None),
+            ],
+        );
+        check(
+            "2024-03-31",
+            &[("-1m", Some("2024-02-29")), ("-1y", Some("2023-03-31"))],
+        );
+        check("2024-02-29", &[("+1y", Some("2025-02-28"))]);
+    }
+
+    #[test]
+    fn dates_with_times() {
+        let today = date("2024-01-25");
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0);
+        assert_eq!(
+            parse_date_time("tomorrowT14:30", &today),
+            Some((date("2024-01-26"), at(14, 30)))
+        );
+        assert_eq!(
+            parse_date_time("2024-02-01T09:05", &today),
+            Some((date("2024-02-01"), at(9, 5)))
+        );
+        assert_eq!(
+            parse_date_time("FriT8:00", &today),
+            Some((date("2024-01-26"), at(8, 0)))
+        );
+        assert_eq!(
+            parse_date_time("fri", &today),
+            Some((date("2024-01-26"), None))
+        );
+        assert_eq!(parse_date_time("tomorrowT25:00", &today), None);
+        assert_eq!(parse_date_time("someT14:30", &today), None);
+    }
+
+    #[test]
+    fn resolves_dates_in_text() {
+        let today = date("2024-01-25");
+        assert_eq!(
+            resolve_dates("Call  mom\t%tomorrow   ^Fri @home ", &today),
+            "Call  mom\t%2024-01-26   ^2024-01-26 @home "
+        );
+        assert_eq!(
+            resolve_dates("  %tomorrowT14:30\n", &today),
+            "  %2024-01-26T14:30\n"
+        );
+        // Only whole words that are dates.
+        assert_eq!(
+            resolve_dates("%someday 50% ^ %% x%today ^fri^", &today),
+            "%someday 50% ^ %% x%today ^fri^"
+        );
+        assert_eq!(resolve_dates("", &today), "");
+    }
+
+    #[test]
+    fn no_dates_past_year_9999() {
+        assert_eq!(add_days(&date("9999-12-31"), 1), None);
+        assert_eq!(add_days(&date("0001-01-01"), -1), None);
+        assert_eq!(add_months(&date("9999-12-01"), 1), None);
+        assert_eq!(add_days(&date("2024-02-28"), 1), Some(date("2024-02-29")));
+        assert_eq!(add_months(&date("2024-01-31"), 1), Some(date("2024-02-29")));
+    }
+}
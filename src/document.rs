@@ -0,0 +1,237 @@
+use crate::task::{indent_width, parse_task, Task};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Line {
+    text: String,
+    /// "\n", "\r\n" or "" for the last line of a file without a trailing newline.
+    ending: String,
+}
+
+/// A file split into lines.
+///
+/// Lines keep their endings, so rendering a document that wasn't edited gives
+/// back the original content byte for byte.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    lines: Vec<Line>,
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Document {
+        let lines = content
+            .split_inclusive('\n')
+            .map(|l| {
+                let text = l
+                    .strip_suffix("\r\n")
+                    .or_else(|| l.strip_suffix('\n'))
+                    .unwrap_or(l);
+                Line {
+                    text: text.to_string(),
+                    ending: l[text.len()..].to_string(),
+                }
+            })
+            .collect();
+        Document { lines }
+    }
+
+    pub fn render(&self) -> String {
+        let mut content = String::new();
+        for l in self.lines.iter() {
+            content.push_str(&l.text);
+            content.push_str(&l.ending);
+        }
+        content
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The text of the line without its ending.
+    pub fn line(&self, i: usize) -> &str {
+        &self.lines[i].text
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|l| l.text.as_str())
+    }
+
+    /// Tasks together with their line numbers (starting from 0).
+    /// Subtasks know their parents, and tasks get their notes.
+    pub fn tasks(&self) -> impl Iterator<Item = (usize, Task)> {
+        let mut tasks: Vec<(usize, Task)> = Vec::new();
+        let mut note_lines: Vec<Vec<usize>> = Vec::new();
+        // Indentation and positions in `tasks` of the tasks whose blocks we are in.
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        for (i, l) in self.lines().enumerate() {
+            if l.trim().is_empty() {
+                continue;
+            }
+            let indent = indent_width(l);
+            while open.last().is_some_and(|(w, _)| *w >= indent) {
+                open.pop();
+            }
+            if let Some(mut task) = parse_task(l) {
+                task.set_line_number(i + 1);
+                task.set_parent(open.last().and_then(|(_, t)| tasks[*t].1.id().cloned()));
+                open.push((indent, tasks.len()));
+                tasks.push((i, task));
+                note_lines.push(Vec::new());
+            } else if let Some((_, t)) = open.last() {
+                note_lines[*t].push(i);
+            }
+        }
+        for ((_, task), lines) in tasks.iter_mut().zip(note_lines) {
+            if let (Some(first), Some(last)) = (lines.first(), lines.last()) {
+                // Blank lines between two notes are kept, subtasks are not.
+                let is_blank = |j: &usize| self.line(*j).trim().is_empty();
+                let notes = (*first..=*last)
+                    .filter(|j| {
+                        lines.contains(j)
+                            || is_blank(j)
+                                && (0..*j)
+                                    .rev()
+                                    .find(|k| !is_blank(k))
+                                    .is_some_and(|k| lines.contains(&k))
+                                && (*j..=*last)
+                                    .find(|k| !is_blank(k))
+                                    .is_some_and(|k| lines.contains(&k))
+                    })
+                    .map(|j| self.line(j).to_string())
+                    .collect();
+                task.set_notes(notes);
+            }
+        }
+        tasks.into_iter()
+    }
+
+    /// The line after the block starting at line `i`. The block is the line
+    /// itself and everything indented deeper below it, e.g. subtasks.
+    pub fn block_end(&self, i: usize) -> usize {
+        let indent = indent_width(self.line(i));
+        let mut end = i + 1;
+        for j in i + 1..self.len() {
+            let l = self.line(j);
+            if l.trim().is_empty() {
+                continue;
+            }
+            if indent_width(l) <= indent {
+                break;
+            }
+            end = j + 1;
+        }
+        end
+    }
+
+    /// Tasks inside the block of the task at line `i`, all levels deep.
+    pub fn subtasks(&self, i: usize) -> impl Iterator<Item = (usize, Task)> {
+        let end = self.block_end(i);
+        self.tasks().filter(move |(j, _)| *j > i && *j < end)
+    }
+
+    /// Removes the block starting at line `i` and returns its lines, with the
+    /// indentation of the first line stripped from all of them.
+    pub fn remove_block(&mut self, i: usize) -> Vec<String> {
+        let end = self.block_end(i);
+        let lines: Vec<String> = self.lines.drain(i..end).map(|l| l.text).collect();
+        dedent(&lines)
+    }
+
+    /// Replaces the text of the line, keeping its ending.
+    pub fn set_line(&mut self, i: usize, text: &str) {
+        self.lines[i].text = text.to_string();
+    }
+
+    pub fn insert(&mut self, i: usize, text: &str) {
+        if i == self.lines.len() {
+            self.push(text);
+            return;
+        }
+        let ending = self.newline().to_string();
+        self.lines.insert(
+            i,
+            Line {
+                text: text.to_string(),
+                ending,
+            },
+        );
+    }
+
+    /// Appends a line to the end of the document.
+    pub fn push(&mut self, text: &str) {
+        let ending = self.newline().to_string();
+        if let Some(last) = self.lines.last_mut() {
+            if last.ending.is_empty() {
+                last.ending = ending.clone();
+            }
+        }
+        self.lines.push(Line {
+            text: text.to_string(),
+            ending,
+        });
+    }
+
+    /// Removes the line and returns its text.
+    pub fn remove(&mut self, i: usize) -> String {
+        self.lines.remove(i).text
+    }
+
+    /// Appends several lines to the end of the document.
+    pub fn extend(&mut self, lines: &[String]) {
+        for l in lines {
+            self.push(l);
+        }
+    }
+
+    /// The line ending used by the file, "\n" if it is not known.
+    fn newline(&self) -> &str {
+        match self.lines.first() {
+            Some(l) if l.ending == "\r\n" => "\r\n",
+            _ => "\n",
+        }
+    }
+}
+
+/// Strips the indentation of the first line from all the lines.
+pub fn dedent(lines: &[String]) -> Vec<String> {
+    let Some(first) = lines.first() else {
+        return Vec::new();
+    };
+    let prefix = &first[..first.len() - first.trim_start().len()];
+    lines
+        .iter()
+        .map(|l| l.strip_prefix(prefix).unwrap_or(l.trim_start()).to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCKS: &str = "# Work\n- [ ] A\n  Note of A\n\n  - [ ] B\n    - [x] C\n\n- [ ] D\n";
+
+    #[test]
+    fn blocks_end_at_the_next_line_as_indented() {
+        let doc = Document::parse(BLOCKS);
+        // The blank line inside the block is part of it, the one after is not.
+        assert_eq!(doc.block_end(1), 6);
+        assert_eq!(doc.block_end(4), 6);
+        assert_eq!(doc.block_end(5), 6);
+        assert_eq!(doc.block_end(7), 8);
+        // A heading has nothing indented below it.
+        assert_eq!(doc.block_end(0), 1);
+    }
+
+    #[test]
+    fn removed_blocks_are_dedented() {
+        let mut doc = Document::parse(BLOCKS);
+        assert_eq!(doc.remove_block(4), ["- [ ] B", "  - [x] C"]);
+        assert_eq!(doc.render(), "# Work\n- [ ] A\n  Note of A\n\n\n- [ ] D\n");
+        assert_eq!(doc.remove_block(1), ["- [ ] A", "  Note of A"]);
+        assert_eq!(doc.render(), "# Work\n\n\n- [ ] D\n");
+    }
+}
@@ -0,0 +1,120 @@
+use crate::id::TaskId;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong when working with a workspace.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io { path: PathBuf, source: io::Error },
+    /// No file in the workspace has a task with this id.
+    TaskNotFound(TaskId),
+    /// The project (a file or a folder) does not exist in the workspace.
+    ProjectNotFound(PathBuf),
+    /// Labels start with @ and have no spaces in them.
+    InvalidLabel(String),
+    /// The string can't be turned into a task.
+    InvalidTask(String),
+    /// A new title has dates, labels, a priority or a recurrence in it.
+    InvalidTitle(String),
+    /// An edit of the task line changed or removed its id.
+    IdChanged(TaskId),
+    /// Priorities are 1 to 4 or A to D.
+    InvalidPriority(String),
+    /// The next instance of a recurring task would be dated past year 9999.
+    DateOutOfRange(TaskId),
+    /// Dates are YYYY-MM-DD or words like tomorrow, see [`crate::dates::parse_date`].
+    InvalidDate(String),
+    /// The query can't be parsed, `at` is the byte offset of the problem.
+    InvalidQuery {
+        query: String,
+        at: usize,
+        message: String,
+    },
+    /// A single word after `rtd` that is not a task, a label, a project or
+    /// a command.
+    UnknownModifier(String),
+    /// The search pattern is not a valid regular expression.
+    InvalidPattern(String),
+    /// The config is missing or broken.
+    Config(String),
+    /// Drawing on the terminal or reading keys failed.
+    Terminal(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::TaskNotFound(id) => write!(f, "Task &{} is not in any of your files", id),
+            Error::ProjectNotFound(path) => {
+                write!(f, "Project {} does not exist.", path.display())
+            }
+            Error::InvalidLabel(label) => write!(
+                f,
+                "A label should start with @ and have no spaces in it, got '{}'.",
+                label
+            ),
+            Error::InvalidTask(task) => write!(f, "Can't parse a task from '{}'.", task),
+            Error::InvalidTitle(title) => write!(
+                f,
+                "The title '{}' can only have words in it, set dates, labels and the rest with their own options.",
+                title
+            ),
+            Error::IdChanged(id) => write!(f, "The id of task &{} can't be changed.", id),
+            Error::InvalidPriority(p) => {
+                write!(f, "Unknown priority '{}', use 1 to 4 or A to D.", p)
+            }
+            Error::DateOutOfRange(id) => write!(
+                f,
+                "Task &{} can't come back, its next date is past year 9999.",
+                id
+            ),
+            Error::InvalidDate(d) => write!(
+                f,
+                "Can't understand the date '{}', use e.g. 2024-01-25, tomorrow, fri, +3d, next-month or eom.",
+                d
+            ),
+            Error::InvalidQuery { query, at, message } => {
+                // Point at the problem under the query.
+                let column = query.get(..*at).map(|q| q.chars().count()).unwrap_or(0);
+                write!(
+                    f,
+                    "Can't parse the query: {}.\n  {}\n  {}^",
+                    message.trim_end_matches('.'),
+                    query,
+                    " ".repeat(column)
+                )
+            }
+            Error::UnknownModifier(modifier) => write!(
+                f,
+                "Unknown modifier: {}. To look for it in titles, use rtd query {}",
+                modifier, modifier
+            ),
+            Error::InvalidPattern(e) => write!(f, "Invalid regular expression: {}", e),
+            Error::Config(msg) => write!(f, "{}", msg),
+            Error::Terminal(source) => write!(f, "Can't use the terminal: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::Terminal(source) => Some(source),
+            _ => None,
+        }
+    }
+}
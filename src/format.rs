@@ -0,0 +1,227 @@
+//! Machine readable output: JSON, NDJSON (a JSON object per line) and CSV.
+//!
+//! Every task becomes an object with its `id`, `status`, `title`, `date`,
+//! `start`, `priority`, `labels`, `project` (the path of its file relative to
+//! the root), `line` (the line number in that file), `parent` and `notes`.
+
+use crate::dates::format_due;
+use crate::task::Task;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The columns of the CSV output of tasks, notes are left out.
+pub const TASK_COLUMNS: [&str; 10] = [
+    "id", "status", "title", "date", "start", "priority", "labels", "project", "line", "parent",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The markdown lines, for people.
+    #[default]
+    Plain,
+    /// A single JSON array.
+    Json,
+    /// A JSON value per line.
+    Ndjson,
+    /// A header and a row per value. Arrays are joined with spaces.
+    Csv,
+}
+
+/// A task as a JSON object, `project` is relative to the root.
+pub fn task_record(task: &Task, project: &Path) -> Value {
+    json!({
+        "id": task.id().map(|id| id.to_string()),
+        "status": task.status().to_string(),
+        "title": task.title(),
+        "date": task.date().map(|d| format_due(d, task.time())),
+        "start": task.start().map(|d| d.to_string()),
+        "priority": task.priority().map(|p| p.level()),
+        "labels": task.labels(),
+        "project": project.to_string_lossy(),
+        "line": task.line_number(),
+        "parent": task.parent().map(|id| id.to_string()),
+        "notes": task.notes(),
+    })
+}
+
+/// Renders `records` in `format`. CSV uses `columns` as the header and takes
+/// those keys from every object; values that are not objects are a single
+/// column. Plain output is up to the caller, this gives the JSON lines for it.
+pub fn render(format: Format, columns: &[&str], records: &[Value]) -> String {
+    match format {
+        Format::Json => Value::Array(records.to_vec()).to_string() + "\n",
+        Format::Plain | Format::Ndjson => records.iter().map(|r| format!("{}\n", r)).collect(),
+        Format::Csv => {
+            let mut out = csv_row(columns.iter().map(|c| c.to_string()));
+            for record in records {
+                let row: Vec<String> = match record {
+                    Value::Object(map) => columns.iter().map(|c| csv_value(map, c)).collect(),
+                    other => vec![csv_cell(other)],
+                };
+                out.push_str(&csv_row(row.into_iter()));
+            }
+            out
+        }
+    }
+}
+
+fn csv_value(map: &Map<String, Value>, column: &str) -> String {
+    map.get(column).map(csv_cell).unwrap_or_default()
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(csv_cell).collect::<Vec<_>>().join(" "),
+        other => other.to_string(),
+    }
+}
+
+/// Quotes the fields that need it, as in RFC 4180.
+fn csv_row(fields: impl Iterator<Item = String>) -> String {
+    let fields: Vec<String> = fields
+        .map(|f| {
+            if f.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", f.replace('"', "\"\""))
+            } else {
+                f
+            }
+        })
+        .collect();
+    fields.join(",") + "\r\n"
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Plain => "plain",
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "plain" => Ok(Format::Plain),
+            "json" => Ok(Format::Json),
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "Unknown format '{}', use plain, json, ndjson or csv.",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::TaskId;
+    use crate::task::parse_task;
+
+    fn records() -> Vec<Value> {
+        let mut task = parse_task(
+            "  - [x] &7 Call \"Bob\", then Ann !2 @phone @work %2024-01-25T8:00 ^2024-01-20",
+        )
+        .unwrap();
+        task.set_line_number(3);
+        task.set_parent(Some(TaskId::Number(4)));
+        task.set_notes(vec![
+            "Ask about\tthe trip".to_string(),
+            "Two lines".to_string(),
+        ]);
+        vec![
+            task_record(&task, Path::new("work/calls.md")),
+            task_record(&parse_task("- [ ] Plain").unwrap(), Path::new("inbox.md")),
+        ]
+    }
+
+    #[test]
+    fn task_records_have_every_field() {
+        let records = records();
+        assert_eq!(
+            records[0],
+            json!({
+                "id": "7",
+                "status": "done",
+                "title": "Call \"Bob\", then Ann",
+                "date": "2024-01-25T08:00",
+                "start": "2024-01-20",
+                "priority": 2,
+                "labels": ["@phone", "@work"],
+                "project": "work/calls.md",
+                "line": 3,
+                "parent": "4",
+                "notes": ["Ask about\tthe trip", "Two lines"],
+            })
+        );
+        // Missing values are there as nulls, so every record has the same keys.
+        let keys = |r: &Value| r.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys(&records[1]), keys(&records[0]));
+        assert_eq!(records[1]["id"], Value::Null);
+        assert_eq!(records[1]["labels"], json!([]));
+        for column in TASK_COLUMNS {
+            assert!(records[0].get(column).is_some(), "{}", column);
+        }
+    }
+
+    #[test]
+    fn json_is_a_single_array() {
+        let out = render(Format::Json, &TASK_COLUMNS, &records());
+        assert_eq!(out.lines().count(), 1);
+        let value: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(value, Value::Array(records()));
+        assert_eq!(render(Format::Json, &TASK_COLUMNS, &[]), "[]\n");
+    }
+
+    #[test]
+    fn ndjson_has_a_record_per_line() {
+        let mut records = records();
+        records[1]["notes"] = json!(["One\nTwo"]);
+        let out = render(Format::Ndjson, &TASK_COLUMNS, &records);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, record) in lines.iter().zip(&records) {
+            assert_eq!(&serde_json::from_str::<Value>(line).unwrap(), record);
+        }
+        assert_eq!(render(Format::Ndjson, &TASK_COLUMNS, &[]), "");
+    }
+
+    #[test]
+    fn csv_quotes_what_needs_it() {
+        assert_eq!(
+            csv_row(
+                ["a", "b,c", "say \"hi\"", "one\ntwo", ""]
+                    .map(String::from)
+                    .into_iter()
+            ),
+            "a,\"b,c\",\"say \"\"hi\"\"\",\"one\ntwo\",\r\n"
+        );
+        let out = render(Format::Csv, &TASK_COLUMNS, &records());
+        assert_eq!(
+            out,
+            "id,status,title,date,start,priority,labels,project,line,parent\r\n\
+             7,done,\"Call \"\"Bob\"\", then Ann\",2024-01-25T08:00,2024-01-20,2,@phone @work,work/calls.md,3,4\r\n\
+             ,todo,Plain,,,,,inbox.md,,\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_takes_the_columns_asked_for() {
+        let records = [json!({"label": "@a,b", "count": 3}), json!("x")];
+        assert_eq!(
+            render(Format::Csv, &["label", "count", "missing"], &records),
+            "label,count,missing\r\n\"@a,b\",3,\r\nx\r\n"
+        );
+    }
+}
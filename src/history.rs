@@ -0,0 +1,207 @@
+//! What rtd remembers between runs that is not in your files: the day every
+//! task last changed, and the day of the last weekly review.
+//!
+//! rtd records the changes it makes itself. Changes made by hand (e.g. in
+//! Obsidian) are noticed later, by a line that differs from the one rtd saw
+//! the last time, and are dated the day rtd noticed them.
+
+use crate::error::{Error, Result};
+use crate::id::TaskId;
+use crate::storage;
+use crate::task::Task;
+use speedate::Date;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+pub const HISTORY_FNAME: &str = ".history";
+// Bump this when the format changes, an old history is dropped.
+const HISTORY_HEADER: &str = "rtd-history 1";
+const REVIEWED: &str = "reviewed";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    /// The day of the last weekly review.
+    pub reviewed: Option<Date>,
+    /// The day a task last changed and a fingerprint of its line then.
+    tasks: HashMap<TaskId, (Date, u64)>,
+}
+
+impl History {
+    /// Reads the history from `root`, an empty one if there is none yet.
+    pub fn load(root: &Path) -> Result<History> {
+        let path = root.join(HISTORY_FNAME);
+        if !path.exists() {
+            return Ok(History::default());
+        }
+        let content = fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+        Ok(History::parse(&content))
+    }
+
+    pub fn save(&self, root: &Path) -> Result<()> {
+        storage::write_atomic(&root.join(HISTORY_FNAME), &self.render())
+    }
+
+    /// Lines that can't be read are skipped, the history is only a hint.
+    fn parse(content: &str) -> History {
+        let mut history = History::default();
+        let mut lines = content.lines();
+        if lines.next() != Some(HISTORY_HEADER) {
+            return history;
+        }
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                [REVIEWED, date] => history.reviewed = Date::parse_str_rfc3339(date).ok(),
+                [id, date, fingerprint] => {
+                    let id = id.strip_prefix('&').and_then(|id| id.parse().ok());
+                    let date = Date::parse_str_rfc3339(date).ok();
+                    let fingerprint = u64::from_str_radix(fingerprint, 16).ok();
+                    if let (Some(id), Some(date), Some(fingerprint)) = (id, date, fingerprint) {
+                        history.tasks.insert(id, (date, fingerprint));
+                    }
+                }
+                _ => {}
+            }
+        }
+        history
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut out = format!("{}\n", HISTORY_HEADER);
+        if let Some(reviewed) = &self.reviewed {
+            out.push_str(&format!("{}\t{}\n", REVIEWED, reviewed));
+        }
+        let mut tasks: Vec<_> = self.tasks.iter().collect();
+        tasks.sort_by(|a, b| a.0.cmp(b.0));
+        for (id, (date, fingerprint)) in tasks {
+            out.push_str(&format!("&{}\t{}\t{:016x}\n", id, date, fingerprint));
+        }
+        out
+    }
+
+    /// The day the task last changed, if rtd has seen it.
+    pub fn changed(&self, task_id: &TaskId) -> Option<&Date> {
+        self.tasks.get(task_id).map(|(date, _)| date)
+    }
+
+    /// Records that the task changed on `today`.
+    pub fn touch(&mut self, task: &Task, today: &Date) {
+        if let Some(id) = task.id() {
+            self.tasks
+                .insert(id.clone(), (today.clone(), fingerprint(task.line())));
+        }
+    }
+
+    /// Notices the tasks changed outside of rtd: a task it hasn't seen yet, or
+    /// one with a different line, counts as changed on `today`.
+    pub fn notice<'a>(&mut self, tasks: impl IntoIterator<Item = &'a Task>, today: &Date) {
+        for task in tasks {
+            let Some(id) = task.id() else { continue };
+            let same = self
+                .tasks
+                .get(id)
+                .is_some_and(|(_, f)| *f == fingerprint(task.line()));
+            if !same {
+                self.touch(task, today);
+            }
+        }
+    }
+
+    /// Forgets the tasks that are gone.
+    pub fn retain(&mut self, ids: &HashSet<TaskId>) {
+        self.tasks.retain(|id, _| ids.contains(id));
+    }
+}
+
+/// FNV-1a of the line without its indentation, a moved subtask is the same
+/// task. It has to stay the same between versions of rtd, so the hasher of
+/// the standard library won't do.
+fn fingerprint(line: &str) -> u64 {
+    line.trim().bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::parse_task;
+
+    fn date(s: &str) -> Date {
+        Date::parse_str(s).unwrap()
+    }
+
+    fn task(line: &str) -> Task {
+        parse_task(line).unwrap()
+    }
+
+    fn changed(history: &History, id: i32) -> Option<String> {
+        history.changed(&TaskId::Number(id)).map(|d| d.to_string())
+    }
+
+    #[test]
+    fn touch_records_the_day() {
+        let mut history = History::default();
+        history.touch(&task("- [ ] &1 A"), &date("2024-01-10"));
+        history.touch(&task("- [ ] B"), &date("2024-01-10"));
+        assert_eq!(changed(&history, 1).as_deref(), Some("2024-01-10"));
+        history.touch(&task("- [x] &1 A"), &date("2024-01-12"));
+        assert_eq!(changed(&history, 1).as_deref(), Some("2024-01-12"));
+        assert_eq!(history.tasks.len(), 1);
+    }
+
+    #[test]
+    fn notice_dates_changes_made_by_hand() {
+        let mut history = History::default();
+        history.touch(&task("- [ ] &1 A"), &date("2024-01-10"));
+        history.touch(&task("- [ ] &2 B"), &date("2024-01-10"));
+        let today = date("2024-02-01");
+        let tasks = [
+            // The same line, moved under another task.
+            task("  - [ ] &1 A"),
+            task("- [ ] &2 B @next"),
+            task("- [ ] &3 C"),
+            task("- [ ] D"),
+        ];
+        history.notice(&tasks, &today);
+        assert_eq!(changed(&history, 1).as_deref(), Some("2024-01-10"));
+        assert_eq!(changed(&history, 2).as_deref(), Some("2024-02-01"));
+        assert_eq!(changed(&history, 3).as_deref(), Some("2024-02-01"));
+        // Nothing changed since.
+        let before = history.clone();
+        history.notice(&tasks, &date("2024-03-01"));
+        assert_eq!(history, before);
+    }
+
+    #[test]
+    fn retain_forgets_tasks_that_are_gone() {
+        let mut history = History::default();
+        for line in ["- [ ] &1 A", "- [ ] &2 B", "- [ ] &k3x7a2q C"] {
+            history.touch(&task(line), &date("2024-01-10"));
+        }
+        let hash = TaskId::Hash("k3x7a2q".to_string());
+        history.retain(&HashSet::from([TaskId::Number(2), hash.clone()]));
+        assert_eq!(changed(&history, 1), None);
+        assert_eq!(changed(&history, 2).as_deref(), Some("2024-01-10"));
+        assert!(history.changed(&hash).is_some());
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let mut history = History {
+            reviewed: Some(date("2024-01-07")),
+            ..Default::default()
+        };
+        history.touch(&task("- [ ] &2 B"), &date("2024-01-10"));
+        history.touch(&task("- [ ] &k3x7a2q C"), &date("2024-01-11"));
+        let content = history.render();
+        assert!(content.starts_with("rtd-history 1\nreviewed\t2024-01-07\n&2\t2024-01-10\t"));
+        assert_eq!(History::parse(&content), history);
+        // Broken lines are skipped, an unknown version is dropped.
+        let broken = format!("{}&3\tyesterday\t0\nnonsense\n", content);
+        assert_eq!(History::parse(&broken), history);
+        let old = content.replace(HISTORY_HEADER, "rtd-history 0");
+        assert_eq!(History::parse(&old), History::default());
+    }
+}
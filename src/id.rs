@@ -0,0 +1,143 @@
+//! Task ids.
+//!
+//! By default ids are numbers: a new task gets the largest id in the workspace
+//! plus one. When two devices add tasks while out of sync, they can hand out the
+//! same number, so there is also a hash mode, where every task gets a short
+//! random base32 id like `&k3x7a2q` that won't collide across devices.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+const HASH_ID_LEN: usize = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TaskId {
+    Number(i32),
+    Hash(String),
+}
+
+/// How new ids are made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdMode {
+    /// The largest id in the workspace plus one.
+    #[default]
+    Number,
+    /// A random base32 string.
+    Hash,
+}
+
+impl TaskId {
+    pub fn as_number(&self) -> Option<i32> {
+        match self {
+            TaskId::Number(n) => Some(*n),
+            TaskId::Hash(_) => None,
+        }
+    }
+
+    /// A new hash id that is not in `taken`. `seed` is mixed into the hash
+    /// together with the current time and the process id.
+    pub fn generate(seed: &str, taken: &HashSet<TaskId>) -> TaskId {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        for attempt in 0u64.. {
+            let mut hasher = DefaultHasher::new();
+            (seed, nanos, std::process::id(), attempt).hash(&mut hasher);
+            let mut bits = hasher.finish();
+            let mut id = String::with_capacity(HASH_ID_LEN);
+            for _ in 0..HASH_ID_LEN {
+                id.push(BASE32_ALPHABET[(bits & 31) as usize] as char);
+                bits >>= 5;
+            }
+            if is_hash_id(&id) {
+                let id = TaskId::Hash(id);
+                if !taken.contains(&id) {
+                    return id;
+                }
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// A hash id has both letters and digits, so that it can't be mistaken for a
+/// number or a word someone wrote after an & in the task title.
+fn is_hash_id(s: &str) -> bool {
+    s.len() == HASH_ID_LEN
+        && s.bytes().all(|b| BASE32_ALPHABET.contains(&b))
+        && s.bytes().any(|b| b.is_ascii_digit())
+        && s.bytes().any(|b| b.is_ascii_lowercase())
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskId::Number(n) => write!(f, "{}", n),
+            TaskId::Hash(h) => write!(f, "{}", h),
+        }
+    }
+}
+
+impl FromStr for TaskId {
+    type Err = String;
+
+    /// Accepts ids with or without the & in front.
+    fn from_str(s: &str) -> Result<TaskId, String> {
+        let s = s.strip_prefix('&').unwrap_or(s);
+        if let Ok(n) = s.parse::<i32>() {
+            Ok(TaskId::Number(n))
+        } else if is_hash_id(s) {
+            Ok(TaskId::Hash(s.to_string()))
+        } else {
+            Err(format!("'{}' is not a task id", s))
+        }
+    }
+}
+
+impl FromStr for IdMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IdMode, String> {
+        match s {
+            "number" | "numbers" => Ok(IdMode::Number),
+            "hash" => Ok(IdMode::Hash),
+            _ => Err(format!("Unknown id mode '{}', use number or hash.", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ids() {
+        assert_eq!("&12".parse(), Ok(TaskId::Number(12)));
+        assert_eq!("12".parse(), Ok(TaskId::Number(12)));
+        assert_eq!("&k3x7a2q".parse(), Ok(TaskId::Hash("k3x7a2q".to_string())));
+        // Not base32, no digit, too short, too long, uppercase.
+        for bad in ["k3x9a2q", "abcdefg", "k3x7a2", "k3x7a2qq", "K3X7A2Q", "&"] {
+            assert!(bad.parse::<TaskId>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn generated_ids_are_new_hash_ids() {
+        let mut taken = HashSet::new();
+        for _ in 0..1000 {
+            let id = TaskId::generate("- [ ] Same line", &taken);
+            let TaskId::Hash(hash) = &id else {
+                panic!("{:?} is not a hash id", id);
+            };
+            assert!(is_hash_id(hash), "{}", hash);
+            assert_eq!(hash.parse::<TaskId>().as_ref(), Ok(&id));
+            assert!(taken.insert(id));
+        }
+    }
+}
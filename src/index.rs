@@ -0,0 +1,372 @@
+//! On-disk cache of parsed tasks.
+//!
+//! Reading every file of a big vault on each run is slow, so the tasks of every
+//! file are kept in the index together with the file's mtime and size. Only the
+//! files whose mtime or size changed since the last run are parsed again.
+//!
+//! A file can change again within the same mtime tick, e.g. `[ ]` -> `[x]` by
+//! another tool right after rtd read it, and then neither changes. So for a
+//! file indexed less than two seconds after it was modified, the index also
+//! keeps a hash of its content, and the file is read to compare it until it's
+//! indexed again later.
+
+use crate::document::Document;
+use crate::error::{Error, Result};
+use crate::id::TaskId;
+use crate::storage;
+use crate::task::{parse_task, Task};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const INDEX_FNAME: &str = ".index";
+// Bump this when the format changes, old indices will be rebuilt.
+const INDEX_HEADER: &str = "rtd-index 4";
+/// The coarsest mtime resolution of common file systems (FAT), two seconds.
+const RACY_NANOS: u128 = 2_000_000_000;
+
+/// A task line with what can't be parsed from the line alone.
+#[derive(Debug, Clone, PartialEq)]
+struct IndexedTask {
+    line_no: usize,
+    parent: Option<TaskId>,
+    line: String,
+    notes: Vec<String>,
+}
+
+/// What we know about a single file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IndexedFile {
+    mtime: u128,
+    size: u64,
+    /// The hash of the content if the file may still change without a new
+    /// mtime, see the module docs.
+    hash: Option<u64>,
+    /// The largest number id, hash ids are not counted.
+    pub max_id: i32,
+    pub labels: Vec<String>,
+    tasks: Vec<IndexedTask>,
+}
+
+impl IndexedFile {
+    fn new(mtime: u128, size: u64, hash: Option<u64>, doc: &Document) -> IndexedFile {
+        let mut file = IndexedFile {
+            mtime,
+            size,
+            hash,
+            max_id: 0,
+            labels: Vec::new(),
+            tasks: Vec::new(),
+        };
+        for (i, task) in doc.tasks() {
+            let id = task.id().and_then(TaskId::as_number).unwrap_or(0);
+            file.max_id = std::cmp::max(id, file.max_id);
+            for l in task.labels() {
+                if !file.labels.contains(l) {
+                    file.labels.push(l.clone());
+                }
+            }
+            file.tasks.push(IndexedTask {
+                line_no: i,
+                parent: task.parent().cloned(),
+                line: task.line().to_string(),
+                notes: task.notes().to_vec(),
+            });
+        }
+        file
+    }
+
+    /// Tasks together with their line numbers.
+    pub fn tasks(&self) -> impl Iterator<Item = (usize, Task)> + '_ {
+        self.tasks.iter().filter_map(|t| {
+            let mut task = parse_task(&t.line)?;
+            task.set_parent(t.parent.clone());
+            task.set_notes(t.notes.clone());
+            task.set_line_number(t.line_no + 1);
+            Some((t.line_no, task))
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Index {
+    files: HashMap<PathBuf, IndexedFile>,
+}
+
+impl Index {
+    /// A missing or broken index is not an error, it is just rebuilt.
+    pub fn load(path: &Path) -> Index {
+        match fs::read_to_string(path) {
+            Ok(content) => Index::parse(&content).unwrap_or_default(),
+            Err(_) => Index::default(),
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&IndexedFile> {
+        self.files.get(path)
+    }
+
+    /// Forgets the files that are not in `files` any more.
+    /// Returns true if anything changed.
+    pub fn prune(&mut self, files: &[PathBuf]) -> bool {
+        let keep: HashSet<&PathBuf> = files.iter().collect();
+        let before = self.files.len();
+        self.files.retain(|path, _| keep.contains(path));
+        self.files.len() != before
+    }
+
+    /// Parses `files` again if they changed. Returns true if anything changed.
+    pub fn refresh(&mut self, files: &[PathBuf]) -> Result<bool> {
+        let now = nanos(SystemTime::now());
+        let mut changed = false;
+        for path in files {
+            let meta = fs::metadata(path).map_err(|e| Error::io(path, e))?;
+            let mtime = meta.modified().map(nanos).unwrap_or(0);
+            let size = meta.len();
+            // A file modified in the future is racy too.
+            let racy = now.saturating_sub(mtime) < RACY_NANOS;
+            let read = || fs::read_to_string(path).map_err(|e| Error::io(path, e));
+            let mut content = None;
+            if let Some(file) = self.files.get_mut(path) {
+                if file.mtime == mtime && file.size == size {
+                    let Some(hash) = file.hash else {
+                        continue;
+                    };
+                    let current = read()?;
+                    if content_hash(&current) == hash {
+                        if !racy {
+                            file.hash = None;
+                            changed = true;
+                        }
+                        continue;
+                    }
+                    content = Some(current);
+                }
+            }
+            let content = match content {
+                Some(content) => content,
+                None => read()?,
+            };
+            let hash = racy.then(|| content_hash(&content));
+            let file = IndexedFile::new(mtime, size, hash, &Document::parse(&content));
+            self.files.insert(path.clone(), file);
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut content = String::from(INDEX_HEADER);
+        content.push('\n');
+        for (fpath, file) in self.files.iter() {
+            let hash = file.hash.map(|h| format!("{:016x}", h));
+            content.push_str(&format!(
+                "F\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                file.mtime,
+                file.size,
+                hash.as_deref().unwrap_or("-"),
+                file.max_id,
+                file.labels.join(" "),
+                fpath.display()
+            ));
+            for t in file.tasks.iter() {
+                let parent = t.parent.as_ref().map(|p| p.to_string()).unwrap_or_default();
+                content.push_str(&format!("T\t{}\t{}\t{}\n", t.line_no, parent, t.line));
+                for note in t.notes.iter() {
+                    content.push_str(&format!("N\t{}\n", note));
+                }
+            }
+        }
+        storage::write_atomic(path, &content)
+    }
+
+    fn parse(content: &str) -> Option<Index> {
+        // Not lines(), a task line can end with \r.
+        let mut lines = content.split_terminator('\n');
+        if lines.next()? != INDEX_HEADER {
+            return None;
+        }
+        let mut files: Vec<(PathBuf, IndexedFile)> = Vec::new();
+        for line in lines {
+            match line.split_once('\t')? {
+                ("F", rest) => {
+                    let mut fields = rest.splitn(6, '\t');
+                    let file = IndexedFile {
+                        mtime: fields.next()?.parse().ok()?,
+                        size: fields.next()?.parse().ok()?,
+                        hash: match fields.next()? {
+                            "-" => None,
+                            h => Some(u64::from_str_radix(h, 16).ok()?),
+                        },
+                        max_id: fields.next()?.parse().ok()?,
+                        labels: fields
+                            .next()?
+                            .split_whitespace()
+                            .map(str::to_string)
+                            .collect(),
+                        tasks: Vec::new(),
+                    };
+                    files.push((PathBuf::from(fields.next()?), file));
+                }
+                ("T", rest) => {
+                    let mut fields = rest.splitn(3, '\t');
+                    let i = fields.next()?.parse().ok()?;
+                    let parent = match fields.next()? {
+                        "" => None,
+                        p => Some(p.parse().ok()?),
+                    };
+                    files.last_mut()?.1.tasks.push(IndexedTask {
+                        line_no: i,
+                        parent,
+                        line: fields.next()?.to_string(),
+                        notes: Vec::new(),
+                    });
+                }
+                ("N", note) => {
+                    let file = &mut files.last_mut()?.1;
+                    file.tasks.last_mut()?.notes.push(note.to_string());
+                }
+                _ => return None,
+            }
+        }
+        Some(Index {
+            files: files.into_iter().collect(),
+        })
+    }
+}
+
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+/// Only compared with a hash made by the same rtd. A new version may hash
+/// differently, then the racy files are parsed again once.
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempRoot;
+    use std::fs::File;
+    use std::time::Duration;
+
+    const CONTENT: &str = "# Project\n\
+        - [ ] &1 Task\twith a tab @home %2024-01-25\n\
+        \tA note\twith a tab\n\
+        \x20 - [x] &2 Subtask !1\n\
+        \x20\x20\x20\x20Its note\n\
+        - [/] &k3x7a2q Hash id\r\r\n\
+        \x20 - [ ] &3 Subtask of a hash id\r\n\
+        Not a task\n\
+        - [ ] No id";
+
+    fn set_mtime(path: &Path, mtime: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    fn titles(index: &Index, path: &Path) -> Vec<String> {
+        let file = index.get(path).unwrap();
+        file.tasks().map(|(_, t)| t.line().to_string()).collect()
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let root = TempRoot::new(&[]);
+        let doc = Document::parse(CONTENT);
+        let mut index = Index::default();
+        for (name, hash) in [
+            ("a file with spaces.md", None),
+            ("dir/b.md", Some(u64::MAX)),
+        ] {
+            let file = IndexedFile::new(1_700_000_000_123_456_789, 42, hash, &doc);
+            index.files.insert(root.join(name), file);
+        }
+        index.files.insert(
+            root.join("empty.md"),
+            IndexedFile::new(0, 0, Some(0), &Document::default()),
+        );
+        let path = root.join(INDEX_FNAME);
+        index.save(&path).unwrap();
+        let loaded = Index::load(&path);
+        assert_eq!(loaded.files, index.files);
+
+        let file = loaded.get(&root.join("dir/b.md")).unwrap();
+        assert_eq!(file.max_id, 3);
+        assert_eq!(file.labels, ["@home"]);
+        let tasks: Vec<Task> = file.tasks().map(|(_, t)| t).collect();
+        let expected: Vec<Task> = doc.tasks().map(|(_, t)| t).collect();
+        assert_eq!(tasks, expected);
+        assert_eq!(tasks[0].notes(), ["A note\twith a tab"]);
+        assert_eq!(tasks[1].parent(), Some(&TaskId::Number(1)));
+        assert_eq!(tasks[2].line(), "- [/] &k3x7a2q Hash id\r");
+        assert_eq!(tasks[3].parent(), Some(&"k3x7a2q".parse().unwrap()));
+    }
+
+    #[test]
+    fn broken_index_is_empty() {
+        let root = TempRoot::new(&[
+            ("old", "rtd-index 3\nF\t1\t2\t0\t\ta.md\n"),
+            ("broken", "rtd-index 4\nT\t0\t\t- [ ] Task without a file\n"),
+        ]);
+        assert!(Index::load(&root.join("old")).files.is_empty());
+        assert!(Index::load(&root.join("broken")).files.is_empty());
+        assert!(Index::load(&root.join("missing")).files.is_empty());
+    }
+
+    #[test]
+    fn notices_edits_within_the_same_mtime() {
+        let root = TempRoot::new(&[("a.md", "- [ ] &1 Task\n")]);
+        let path = root.join("a.md");
+        let files = [path.clone()];
+        let mut index = Index::default();
+        assert!(index.refresh(&files).unwrap());
+        // Just written, so the content may change without a new mtime.
+        assert!(index.get(&path).unwrap().hash.is_some());
+        assert!(!index.refresh(&files).unwrap());
+
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+        root.write("a.md", "- [x] &1 Task\n");
+        set_mtime(&path, mtime);
+        assert!(index.refresh(&files).unwrap());
+        assert_eq!(titles(&index, &path), ["- [x] &1 Task"]);
+
+        // Once the file is old enough, its hash isn't needed any more.
+        let past = SystemTime::now() - Duration::from_secs(60);
+        set_mtime(&path, past);
+        assert!(index.refresh(&files).unwrap());
+        assert!(index.get(&path).unwrap().hash.is_none());
+        assert!(!index.refresh(&files).unwrap());
+    }
+
+    #[test]
+    fn drops_the_hash_of_an_unchanged_file_once_it_is_old() {
+        let root = TempRoot::new(&[("a.md", "- [ ] &1 Task\n")]);
+        let path = root.join("a.md");
+        let files = [path.clone()];
+        let mut index = Index::default();
+        index.refresh(&files).unwrap();
+        let file = index.files.get_mut(&path).unwrap();
+        // Indexed back then, when the file was new.
+        let past = SystemTime::now() - Duration::from_secs(60);
+        file.mtime = nanos(past);
+        set_mtime(&path, past);
+        // The content is the same, only the hash goes.
+        assert!(index.refresh(&files).unwrap());
+        assert!(index.get(&path).unwrap().hash.is_none());
+        assert_eq!(titles(&index, &path), ["- [ ] &1 Task"]);
+    }
+}
@@ -0,0 +1,38 @@
+//! rtd keeps your todos in plain markdown files.
+//!
+//! A [`Workspace`] is a folder tree of markdown files (projects), and every
+//! `- [ ]` line in them is a [`Task`]. The `rtd` binary is a thin CLI over
+//! this library, so you can build your own tools on the same files.
+
+pub mod agenda;
+pub mod config;
+pub mod dates;
+pub mod document;
+pub mod error;
+pub mod format;
+pub mod history;
+pub mod id;
+mod index;
+pub mod process;
+pub mod query;
+pub mod recurrence;
+pub mod review;
+pub mod storage;
+pub mod style;
+pub mod task;
+#[cfg(test)]
+mod testing;
+pub mod tui;
+pub mod view;
+pub mod workspace;
+
+pub use config::Config;
+pub use dates::today;
+pub use document::Document;
+pub use error::{Error, Result};
+pub use id::{IdMode, TaskId};
+pub use query::Query;
+pub use recurrence::{Interval, Recurrence};
+pub use task::{parse_task, sort_tasks, Priority, Task, TaskStatus};
+pub use view::View;
+pub use workspace::{Change, Filter, IdChange, Modification, Modified, Project, Workspace};
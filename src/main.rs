@@ -1,32 +1,49 @@
-use chrono::prelude::*;
 use clap::{Parser, Subcommand};
 use regex::Regex;
+use rtd::agenda::{agenda, Section};
+use rtd::dates::{add_days, now, parse_duration, to_naive};
+use rtd::format::{render, task_record, Format, TASK_COLUMNS};
+use rtd::review::{ProjectReview, Review};
+use rtd::style::{self, ColorChoice};
+use rtd::task::is_valid_label;
+use rtd::view::{render as fill, View};
+use rtd::workspace::INBOX_FNAME;
+use rtd::{process, tui};
+use rtd::{
+    sort_tasks, today, Change, Config, Filter, IdChange, Modification, Modified, Priority, Project,
+    Query, Task, TaskId, TaskStatus, Workspace,
+};
+use serde_json::{json, Value};
 use speedate::Date;
-use std::collections::HashSet;
 use std::fmt;
-use std::env;
-use std::fs::OpenOptions;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::{Path, PathBuf};
-
-const CONFIG_FNAME: &str = ".rtd";
-const INBOX_FNAME: &str = "inbox.md";
-const RTD_ROOT_VAR_NAME: &str = "RTD_ROOT";
-const TASK_UNDONE: &str = "- [ ]";
-const TASK_DONE: &str = "- [x]";
-const DONE_TASKS_FNAME: &str = ".done";
-// SERVICE_FNAMES files will be ignored when iterating over files.
-// They are used by rtd for bookkeeping.
-const SERVICE_FNAMES: [&str; 1] = [DONE_TASKS_FNAME];
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::Command;
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(subcommand_required = false, arg_required_else_help = false)]
 struct Cli {
     #[command(subcommand)]
     command: Option<SubcommandEnum>,
-    /// This can be a task id, @label or a project (e.g. file.md).
+    /// This can be a task id, @label, a project (e.g. file.md) or a query (see rtd query).
     global_modifier: Option<String>,
+    /// Only show tasks with this status: todo, done, cancelled, in-progress or forwarded.
+    #[arg(long, global = true)]
+    status: Option<TaskStatus>,
+    /// Only show tasks with this priority or a higher one (1 is the highest).
+    #[arg(long, global = true)]
+    min_priority: Option<Priority>,
+    /// Also show tasks that start after today.
+    #[arg(long, global = true)]
+    all: bool,
+    /// Output format: plain, json, ndjson or csv.
+    #[arg(long, global = true, default_value_t = Format::Plain)]
+    format: Format,
+    /// Colour the output: auto (on a terminal), always or never.
+    #[arg(long, global = true, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
 }
 
 #[derive(Debug, Subcommand)]
@@ -37,551 +54,1011 @@ enum SubcommandEnum {
     /// Show all tasks in the workspace.
     #[command(visible_alias = "a")]
     All,
+    /// Show tasks matching a query, e.g. rtd query '@next and not @waiting and due<=+7d'.
+    /// Conditions: @label, "text", project:work/, status:done, has:date, due<=today,
+    /// start>+1w, prio<=2, combined with and, or, not and parentheses.
+    #[command(visible_alias = "q")]
+    Query {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        query: Vec<String>,
+    },
+    /// Find tasks by words in their titles, labels and notes, ignoring case.
+    Search {
+        pattern: String,
+        /// The pattern is a regular expression (case sensitive, unless it starts with (?i)).
+        #[arg(long)]
+        regex: bool,
+        /// Search the archive of done tasks too.
+        #[arg(long)]
+        archive: bool,
+    },
+    /// Show a view saved in the config, e.g. view.today = due<=today and not @someday.
+    #[command(name = "view", visible_alias = "v")]
+    ShowView { name: String },
+    /// List the views saved in the config.
+    Views,
+    /// Show tasks that start after today.
+    Tickler,
+    /// Show open tasks that are overdue or due in the next days, by day.
+    Agenda {
+        /// How many days to show, today included, at most ten years.
+        #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u32).range(..=3660))]
+        days: u32,
+    },
     /// Show due tasks.
-    Due,
+    Due {
+        /// Only what is due in this much time (e.g. 30m, 2h, 3d, 1w) or earlier, counting
+        /// times of day too.
+        #[arg(long, value_parser = parse_within)]
+        within: Option<chrono::Duration>,
+    },
     ///Print an URL if a task description has one. Provide task id.
-    Url { task_id: i32 },
+    Url { task_id: TaskId },
     ///Remove task. Provide task id.
-    Rm { task_id: i32 },
+    Rm { task_id: TaskId },
     ///Print out a list of all projects.
     List,
-    ///Show all labels.
-    Labels,
+    ///Show all labels with the number of tasks having them, or rename or delete a label.
+    Labels {
+        #[command(subcommand)]
+        action: Option<LabelAction>,
+    },
     ///Add a task. <task_description> <project>. If project not provided, adding to inbox. Task
-    ///description can have a date (starts with %), and labels (each starts with @, no spaces
-    ///allowed).
+    ///description can have a date (starts with %, e.g. %2024-01-25 or %tomorrow), and labels
+    ///(each starts with @, no spaces allowed).
     Add {
         task_description: String,
         project: Option<String>,
     },
     ///Add a label to a task. <task_id> <label>. Label starts with @.
     #[command(visible_alias = "al")]
-    AddLabel { task_id: i32, label: String },
+    AddLabel { task_id: TaskId, label: String },
+    ///Remove a label from a task. <task_id> <label>.
+    #[command(visible_alias = "rl")]
+    RemoveLabel { task_id: TaskId, label: String },
     ///Move a task to a project: <task_id> <project>.
-    Mv { task_id: i32, project: String },
+    Mv { task_id: TaskId, project: String },
     ///Move done tasks to archive.
     Archive,
     ///Toggle task status (done -> undone, undone -> done).
     #[command(visible_alias = "t")]
-    Toggle { task_id: i32 },
+    Toggle {
+        task_id: TaskId,
+        /// Give subtasks the same status.
+        #[arg(short, long)]
+        subtasks: bool,
+    },
+    ///Set task status: <task_id> <status>. Status is todo, done, cancelled, in-progress or
+    ///forwarded.
+    #[command(visible_alias = "s")]
+    Status {
+        task_id: TaskId,
+        status: TaskStatus,
+        /// Give subtasks the same status.
+        #[arg(short, long)]
+        subtasks: bool,
+    },
+    ///Set task priority: <task_id> <level>. Level is 1 (highest) to 4, or A to D. Use none to
+    ///remove the priority.
+    Prio { task_id: TaskId, level: String },
+    ///Set task date: <task_id> <when> [time]. When is YYYY-MM-DD, today, tomorrow, fri, +3d,
+    ///-1w, next-month, eom and the like, time is HH:MM. Use none to remove the date.
+    Date {
+        task_id: TaskId,
+        #[arg(allow_hyphen_values = true)]
+        when: String,
+        /// Time of day, HH:MM.
+        time: Option<String>,
+    },
     ///Toggle task date (change for today!)
     #[command(visible_alias = "td")]
-    ToggleDate { task_id: i32 },
+    ToggleDate { task_id: TaskId },
+    ///Change the title, date or labels of a task. Without options, edit its line in $EDITOR.
+    Edit {
+        task_id: TaskId,
+        /// The new title, labels and dates stay. Only words, no labels or dates.
+        #[arg(long)]
+        title: Option<String>,
+        /// The new date, e.g. 2024-01-25, tomorrow, fri or +3d, with an optional THH:MM.
+        #[arg(long, allow_hyphen_values = true, conflicts_with = "clear_date")]
+        date: Option<String>,
+        /// Remove the date.
+        #[arg(long)]
+        clear_date: bool,
+        /// Add a label, can be repeated.
+        #[arg(long)]
+        add_label: Vec<String>,
+        /// Remove a label, can be repeated.
+        #[arg(long)]
+        rm_label: Vec<String>,
+    },
+    ///Give ids to the tasks that don't have one yet, and new ids to the tasks sharing an id.
+    Ids,
+    ///Browse and change your tasks in a full-screen interface.
+    Tui,
+    ///Go through the inbox and decide what to do with every task.
+    Process,
+    ///Go through every project and see what needs attention, the weekly review.
+    Review {
+        /// Tasks that didn't change for this many days are stale.
+        #[arg(long, default_value_t = 14)]
+        days: i64,
+        /// Inbox tasks are stale after this many days.
+        #[arg(long, default_value_t = 3)]
+        inbox_days: i64,
+    },
 }
 
-struct TaskStats {
-    max_id: i32,
+impl SubcommandEnum {
+    /// Read-only commands never touch the files.
+    fn writes(&self) -> bool {
+        !matches!(
+            self,
+            SubcommandEnum::Inbox
+                | SubcommandEnum::All
+                | SubcommandEnum::Query { .. }
+                | SubcommandEnum::Search { .. }
+                | SubcommandEnum::ShowView { .. }
+                | SubcommandEnum::Views
+                | SubcommandEnum::Tickler
+                | SubcommandEnum::Agenda { .. }
+                | SubcommandEnum::Due { .. }
+                | SubcommandEnum::Url { .. }
+                | SubcommandEnum::List
+                | SubcommandEnum::Labels { action: None }
+                | SubcommandEnum::Labels {
+                    action: Some(
+                        LabelAction::Rename { dry_run: true, .. }
+                            | LabelAction::Delete { dry_run: true, .. }
+                    )
+                }
+        )
+    }
 }
-//TODO: check if negative ids are properly processed.
 
-struct Task {
-    is_done: bool,
-    id: i32,
-    title: String,
-    date: Option<Date>,
-    labels: Vec<String>,
+#[derive(Debug, Subcommand)]
+enum LabelAction {
+    ///Rename a label in every task: <old> <new>.
+    Rename {
+        old: String,
+        new: String,
+        /// Only show the tasks that would change.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    ///Remove a label from every task.
+    Delete {
+        label: String,
+        /// Only show the tasks that would change.
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
-impl fmt::Display for Task {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let status = if self.is_done { TASK_DONE } else { TASK_UNDONE };
-        write!(f, "{} &{} {}", status, self.id, self.title)?;
+fn parse_within(s: &str) -> Result<chrono::Duration, String> {
+    parse_duration(s).ok_or_else(|| format!("'{}' is not a duration, use e.g. 30m, 2h or 3d", s))
+}
 
-        if let Some(date) = &self.date {
-            write!(f, " %{}", date.clone())?;
-        }
+/// Prints what commands return: markdown lines for people, or records for
+/// programs with --format.
+struct Output<'a> {
+    ws: &'a Workspace,
+    format: Format,
+    color: bool,
+    today: Date,
+}
 
-        if !self.labels.is_empty() {
-            for l in self.labels.iter() {
-                // We store labels together with the @ sign.
-                write!(f, " {}", l).expect("Failed to write to task_string");
-            }
-        }
-        Ok(())
+impl Output<'_> {
+    fn is_plain(&self) -> bool {
+        self.format == Format::Plain
     }
-}
 
-fn parse_task(line: &str) -> Option<Task> {
-    if line.starts_with(TASK_DONE) || line.starts_with(TASK_UNDONE) {
-        let mut line_to_parse = line;
-        let status = line_to_parse.starts_with(TASK_DONE);
-        //TODO: replace this by taking the length of the TASK_DONE/UNDONE.
-        //otherwise, it will fail when these two are changed, though this is unlikely.
-        line_to_parse = &line_to_parse[5..];
-        let mut split_string = line_to_parse.split_whitespace();
-        let potential_id = split_string.next()?;
-        let mut id = -1;
-        let split_string_vec = split_string.clone().collect::<Vec<&str>>();
-        let mut task_body_vec: Vec<&str> = Vec::new();
-        let mut task_date: Option<Date> = None;
-        let mut labels: Vec<String> = Vec::new();
-        if potential_id.starts_with('&') {
-            id = (potential_id.strip_prefix('&'))?.parse().unwrap();
+    /// The task line, coloured and with its id aligned to `id_width` on a
+    /// terminal, the markdown line otherwise.
+    fn line(&self, task: &Task, id_width: usize) -> String {
+        if self.color {
+            style::task_line(task, &self.today, id_width)
         } else {
-            task_body_vec.push(potential_id);
+            task.to_string()
         }
-        for v in split_string_vec.iter() {
-            if v.starts_with('%') {
-                let date = Date::parse_str_rfc3339(v.strip_prefix('%')?);
-                if date.is_ok() {
-                    task_date = Some(date.expect(""));
-                } else {
-                    task_body_vec.push(v);
-                }
-            } else if v.starts_with('@') {
-                labels.push(v.to_string());
-            } else {
-                task_body_vec.push(v);
-            }
-        }
-
-        let task = Task {
-            id,
-            title: task_body_vec.join(" "),
-            is_done: status,
-            date: task_date,
-            labels,
-        };
+    }
 
-        Some(task)
-    } else {
-        None
+    fn heading(&self, heading: impl fmt::Display) {
+        let heading = format!("####### {} #######", heading);
+        if self.color {
+            println!("{}", style::heading(&heading));
+        } else {
+            println!("{}", heading);
+        }
     }
-}
 
-fn today() -> Date {
-    let today = Local::now().format("%Y-%m-%d");
-    Date::parse_str_rfc3339(&today.to_string()).expect("Can't parse today's date.")
-}
+    /// A task on its own, e.g. the one a command has just changed.
+    fn task(&self, task: &Task) {
+        println!("{}", self.line(task, 0));
+    }
 
-fn get_file_tasks(fname: &Path, due_only: bool, label: Option<String>) -> Vec<Task> {
-    let file = File::open(fname).unwrap();
-    let mut file_tasks = Vec::new();
-    let reader = BufReader::new(file);
-    let speedate_today = today();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        if let Some(task) = parse_task(&line) {
-            if due_only && (task.date.is_none() || task.date.clone().unwrap() > speedate_today) {
-                continue;
-            }
-            if label.is_some() && !task.labels.contains(&label.clone().unwrap()) {
-                continue;
-            }
-            file_tasks.push(task);
+    /// Messages for people. With a machine readable format they go to stderr,
+    /// so that they don't get mixed with the records.
+    fn message(&self, message: &str) {
+        if self.is_plain() {
+            println!("{}", message);
+        } else {
+            eprintln!("{}", message);
         }
     }
-    file_tasks
-}
 
-fn show_file_tasks(fname: &Path, due_only: bool, label: Option<String>) {
-    let file_tasks = get_file_tasks(fname, due_only, label);
-    if !file_tasks.is_empty() {
-        println!("####### {} #######", fname.to_str().expect(""));
+    fn record(&self, path: &Path, task: &Task) -> Value {
+        task_record(task, self.ws.relative(path))
     }
-    for task in file_tasks {
-        println!("{}", task);
+
+    fn records(&self, records: &[Value]) {
+        print!("{}", render(self.format, &TASK_COLUMNS, records));
     }
-}
 
-fn get_file_labels(fname: &Path) -> HashSet<String> {
-    let mut labels: HashSet<String> = HashSet::new();
-    let file_tasks = get_file_tasks(fname, false, None);
-    for t in file_tasks {
-        for l in t.labels {
-            labels.insert(l);
+    /// Tasks that a command changed, the plain output is up to the command.
+    fn changed(&self, path: &Path, tasks: &[&Task]) {
+        if !self.is_plain() {
+            let records: Vec<Value> = tasks.iter().map(|t| self.record(path, t)).collect();
+            self.records(&records);
         }
     }
-    labels
-}
 
-fn get_all_files(dir: &Path) -> Vec<PathBuf> {
-    let mut all_files: Vec<PathBuf> = Vec::new();
-    if dir.is_dir() {
-        let mut dirs = Vec::new();
-        dirs.push(dir.to_path_buf());
-        while let Some(current_dir) = dirs.pop() {
-            for entry in fs::read_dir(current_dir).expect("").flatten() {
-                let path = entry.path().to_owned();
-                if path.is_dir() {
-                    dirs.push(path);
-                } else {
-                    let mut is_service = false;
-                    for el in SERVICE_FNAMES {
-                        // Ideally, this el has to be joined with root_path,
-                        // but I was lazy.
-                        if path.ends_with(el) {
-                            is_service = true;
-                            break;
-                        }
-                    }
-                    if !is_service {
-                        all_files.push(path);
-                    }
-                }
-            }
-        }
+    fn modified(&self, modified: &Modified) {
+        let mut tasks = vec![&modified.task];
+        tasks.extend(modified.next.iter());
+        self.changed(&modified.path, &tasks);
     }
-    all_files
-}
 
-fn initialise(root_path: &Path) -> TaskStats {
-    let mut stats = TaskStats { max_id: 0 };
-    for fpath in get_all_files(root_path) {
-        let ftasks = get_file_tasks(&fpath, false, None);
-        for t in ftasks {
-            stats.max_id = std::cmp::max(t.id, stats.max_id);
+    fn projects(&self, projects: &[Project]) {
+        if !self.is_plain() {
+            let records: Vec<Value> = projects
+                .iter()
+                .flat_map(|p| p.tasks.iter().map(|t| self.record(&p.path, t)))
+                .collect();
+            self.records(&records);
+            return;
+        }
+        for project in projects {
+            self.heading(project.path.display());
+            let width = style::id_width(&project.tasks);
+            for task in project.tasks.iter() {
+                println!("{}", self.line(task, width));
+            }
         }
     }
 
-    // TODO: go through the tasks and set ids if not set.
-    // Go through all the files and replace task lines with modified.
-    // Leave non-task lines untouched.
-    let mut ids: HashSet<i32> = HashSet::new();
-    for fpath in get_all_files(root_path) {
-        let content = fs::read_to_string(&fpath).expect("Can't read the file");
-        let lines: Vec<_> = content.lines().collect();
-        let of = File::create(fpath).unwrap();
-        let mut writer = BufWriter::new(&of);
-        for l in lines {
-            if let Some(mut task) = parse_task(l) {
-                if task.id < 0 && ids.contains(&task.id) {
-                    task.id = stats.max_id + 1;
-                    stats.max_id += 1;
-                }
-                ids.insert(task.id);
-                writeln!(writer, "{}", task).unwrap();
-            } else {
-                writeln!(writer, "{}", l).unwrap();
+    /// Plain strings, one per line, or a single column.
+    fn strings(&self, column: &str, values: impl Iterator<Item = String>) {
+        if self.is_plain() {
+            for value in values {
+                println!("{}", value);
             }
+        } else {
+            let records: Vec<Value> = values.map(Value::String).collect();
+            print!("{}", render(self.format, &[column], &records));
         }
     }
+}
 
-    let done_file_path = root_path.join(DONE_TASKS_FNAME);
-    if !done_file_path.exists() {
-        println!("There is no {DONE_TASKS_FNAME} file in the root. Creating...");
-        let mut f = fs::File::create(done_file_path.clone()).unwrap();
-        f.write_all("".as_bytes()).expect("");
+/// Shows the next instance of a recurring task that was just done.
+fn show_next(out: &Output, modified: &Modified) {
+    if let Some(next) = &modified.next {
+        println!("Next time:");
+        out.task(next);
     }
-
-    stats
 }
 
-fn move_task(task_id: i32, root_path: &Path, dest_fpath: &Path) {
-    let mut found = false;
-    let dest_path = root_path.join(dest_fpath);
-    if !dest_path.exists() {
-        eprintln!("Destination file does not exist.");
+/// Prints a single task with its notes.
+fn show_task(out: &Output, path: &Path, task: &Task) {
+    if !out.is_plain() {
+        out.records(&[out.record(path, task)]);
         return;
     }
-    for fpath in get_all_files(root_path) {
-        let content = fs::read_to_string(&fpath).expect("Can't read the file");
-        let lines: Vec<_> = content.lines().collect();
-        let of = File::create(fpath).unwrap();
-        let mut writer = BufWriter::new(&of);
-        for l in lines {
-            if let Some(task) = parse_task(l) {
-                if task.id != task_id {
-                    writeln!(writer, "{}", l).unwrap();
-                } else {
-                    println!("{}", task);
-                    println!(
-                        "Task &{} is moved to the list {}",
-                        task_id,
-                        dest_fpath.to_str().unwrap()
-                    );
-                    let mut dest_file = OpenOptions::new()
-                        .append(true)
-                        .open(root_path.join(dest_fpath))
-                        .unwrap();
-                    writeln!(dest_file, "{l}").unwrap();
-                    found = true;
-                }
-            } else {
-                writeln!(writer, "{}", l).unwrap();
-            }
-        }
-        if found {
-            break;
+    out.task(task);
+    for note in task.notes() {
+        if note.is_empty() {
+            println!();
+        } else {
+            println!("    {}", note);
         }
     }
-    if !found {
-        println!("Task &{} is not in any of your files", task_id);
-    };
 }
 
-fn remove_task(task_id: i32, root_path: &Path) {
-    let mut found = false;
-    for fpath in get_all_files(root_path) {
-        let content = fs::read_to_string(&fpath).expect("Can't read the file");
-        let lines: Vec<_> = content.lines().collect();
-        let of = File::create(fpath).unwrap();
-        let mut writer = BufWriter::new(&of);
-        for l in lines {
-            if let Some(task) = parse_task(l) {
-                if task.id != task_id {
-                    writeln!(writer, "{}", task).unwrap();
+fn show(out: &Output, scope: &Path, filter: &Filter) -> rtd::Result<()> {
+    let mut projects = out.ws.query(scope, filter)?;
+    for project in projects.iter_mut() {
+        sort_tasks(&mut project.tasks);
+    }
+    out.projects(&projects);
+    Ok(())
+}
+
+/// Prints the tasks with their notes, the matches are highlighted on a terminal.
+fn show_matches(out: &Output, projects: &[Project], re: &Regex) {
+    if !out.is_plain() {
+        return out.projects(projects);
+    }
+    let highlight = |text: &str| -> String {
+        if !out.color {
+            return text.to_string();
+        }
+        re.replace_all(text, "\x1b[7m$0\x1b[0m").into_owned()
+    };
+    for project in projects {
+        out.heading(project.path.display());
+        for task in project.tasks.iter() {
+            println!("{}", highlight(task.line()));
+            for note in task.notes() {
+                if note.is_empty() {
+                    println!();
                 } else {
-                    println!("{}", task);
-                    println!("Task &{} is removed from the list", task_id);
-                    found = true;
+                    println!("    {}", highlight(note));
                 }
-            } else {
-                writeln!(writer, "{}", l).unwrap();
             }
         }
-        if found {
-            break;
-        }
     }
-    if !found {
-        println!("Task &{} is not in any of your files", task_id);
-    };
 }
 
-fn add_task(task_str: &str, fpath: &Path, mut stats: TaskStats) {
-    let content = fs::read_to_string(fpath).expect("Can't read the file");
-    let lines: Vec<_> = content.lines().collect();
-    let of = File::create(fpath).unwrap();
-    let mut writer = BufWriter::new(&of);
-    //todo append status string here
-    let mut task_string = String::from(TASK_UNDONE);
-    task_string.push(' ');
-    task_string.push_str(task_str);
-    let mut task_to_write = parse_task(&task_string).unwrap();
-    task_to_write.id = stats.max_id + 1;
-    stats.max_id += 1;
-    println!("Added new task to {}:", fpath.to_str().unwrap());
-    println!("{}", task_to_write);
-    writeln!(writer, "{}", task_to_write).unwrap();
-    for l in lines {
-        writeln!(writer, "{}", l).unwrap();
+fn show_view(out: &Output, view: &View, filter: &Filter) -> rtd::Result<()> {
+    let query = (!view.query.trim().is_empty())
+        .then(|| Query::parse(&view.query, &today()))
+        .transpose()?;
+    let filter = Filter {
+        query,
+        ..filter.clone()
+    };
+    let projects = out.ws.query(Path::new(""), &filter)?;
+    let groups = view.arrange(projects);
+    if !out.is_plain() {
+        let records: Vec<Value> = groups
+            .iter()
+            .flat_map(|g| g.tasks.iter().map(|(path, task)| out.record(path, task)))
+            .collect();
+        out.records(&records);
+        return Ok(());
     }
-}
-
-fn modify_task(
-    task_id: i32,
-    root_path: &Path,
-    label_to_add: Option<String>,
-    toggle_status: bool,
-    toggle_date: bool,
-) {
-    for fpath in get_all_files(root_path) {
-        let content = fs::read_to_string(&fpath).expect("Can't read the file");
-        let lines: Vec<_> = content.lines().collect();
-        let of = File::create(fpath).unwrap();
-        let mut writer = BufWriter::new(&of);
-        for l in lines {
-            if let Some(mut task) = parse_task(l) {
-                if task.id == task_id {
-                    // This branch is doing all the modifications.
-                    // If the argument is Some, update the task with it.
-                    if let Some(label) = label_to_add.clone() {
-                        task.labels.push(label);
-                    }
-                    if toggle_status {
-                        task.is_done = !task.is_done;
-                        println!("Changed status of the task {}", task_id);
-                        println!("Current state:");
-                        println!("{}", task);
-                    }
-                    if toggle_date {
-                        if task.date.is_some() {
-                            task.date = None;
-                        } else {
-                            task.date = Some(today());
-                        }
-                    }
-                }
-                writeln!(writer, "{}", task).unwrap();
-            } else {
-                writeln!(writer, "{}", l).unwrap();
+    for group in groups {
+        if let Some(heading) = &group.heading {
+            out.heading(heading);
+        }
+        let width = style::id_width(group.tasks.iter().map(|(_, t)| t));
+        for (path, task) in group.tasks.iter() {
+            match &view.format {
+                Some(template) => println!("{}", fill(template, task, out.ws.relative(path))),
+                None => println!("{}", out.line(task, width)),
             }
         }
     }
+    Ok(())
 }
 
-fn get_task(task_id: i32, root_path: &Path) -> Option<Task> {
-    for fpath in get_all_files(root_path) {
-        let content = fs::read_to_string(&fpath).expect("Can't read the file");
-        let lines: Vec<_> = content.lines().collect();
-        for l in lines {
-            if let Some(task) = parse_task(l) {
-                if task.id == task_id {
-                    return Some(task);
-                }
+fn show_agenda(out: &Output, filter: &Filter, days: u32) -> rtd::Result<()> {
+    let today = today();
+    let projects = out.ws.query(Path::new(""), filter)?;
+    let sections = agenda(projects, &today, days);
+    if !out.is_plain() {
+        let records: Vec<Value> = sections
+            .iter()
+            .flat_map(|(_, entries)| entries.iter())
+            .map(|e| {
+                let mut record = out.record(&e.path, &e.task);
+                record["overdue_days"] = e.overdue_days(&today).into();
+                record
+            })
+            .collect();
+        out.records(&records);
+        return Ok(());
+    }
+    for (i, (section, entries)) in sections.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let heading = match section {
+            Section::Overdue => "Overdue".to_string(),
+            Section::Day(date) if date == today => "Today".to_string(),
+            Section::Day(date) if Some(&date) == add_days(&today, 1).as_ref() => {
+                "Tomorrow".to_string()
+            }
+            Section::Day(date) => format!("{} {}", to_naive(&date).format("%A"), date),
+        };
+        out.heading(heading);
+        let width = style::id_width(entries.iter().map(|e| &e.task));
+        for entry in entries {
+            let task = out.line(&entry.task, width);
+            let project = out.ws.relative(&entry.path).display();
+            match entry.overdue_days(&today) {
+                0 => println!("{} ({})", task, project),
+                1 => println!("{} ({}, 1 day overdue)", task, project),
+                n => println!("{} ({}, {} days overdue)", task, project, n),
             }
         }
     }
-    None
+    Ok(())
 }
 
-fn archive_tasks(root_path: &Path) {
-    let mut done_file = OpenOptions::new()
-        .append(true)
-        .open(root_path.join(DONE_TASKS_FNAME))
-        .unwrap();
-
-    for fpath in get_all_files(root_path) {
-        let content = fs::read_to_string(&fpath).expect("Can't read the file");
-        let lines: Vec<_> = content.lines().collect();
-        let of = File::create(fpath.clone()).unwrap();
-        let mut writer = BufWriter::new(&of);
-        for l in lines {
-            if let Some(task) = parse_task(l) {
-                let mut task_string = task.to_string();
-                if task.is_done {
-                    task_string.push(' ');
-                    task_string.push_str(fpath.to_str().unwrap());
-                    writeln!(done_file, "{task_string}").unwrap();
-                } else {
-                    writeln!(writer, "{task_string}").unwrap();
-                }
-            } else {
-                writeln!(writer, "{}", l).unwrap();
+/// Prints the review a project at a time, waiting for enter between them on a
+/// terminal. Returns true if the user went through it to the end; records for
+/// programs are not a review, so they return false.
+fn show_review(out: &Output, review: &Review, days: i64, inbox_days: i64) -> bool {
+    if !out.is_plain() {
+        let mut records = Vec::new();
+        for project in review.projects.iter() {
+            let path = out.ws.root().join(&project.path);
+            let mut flagged: Vec<(&Task, &str, Option<i64>)> = Vec::new();
+            flagged.extend(project.waiting.iter().map(|t| (t, "waiting", None)));
+            flagged.extend(project.overdue.iter().map(|t| (t, "overdue", None)));
+            flagged.extend(project.stale.iter().map(|(t, d)| (t, "stale", Some(*d))));
+            for (task, flag, days) in flagged {
+                let mut record = out.record(&path, task);
+                record["flag"] = flag.into();
+                record["untouched_days"] = days.into();
+                records.push(record);
+            }
+            if project.open == 0 {
+                out.message(&format!("{} has no open tasks", project.path.display()));
             }
         }
+        out.records(&records);
+        return false;
     }
-}
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // I have no idea what's going on and why we need to unwrap twice.
-    // I am also surprised that to get your home directory, you need a crate.
-    let config_path = env::home_dir().expect("I need a $HOME to operate.");
-    if config_path.exists() {
-        // Initialisation starts
-        // When config grows, we'll need to read file line by line.
-        let contents = fs::read_to_string(config_path.join(CONFIG_FNAME)).expect("");
-        let line: Vec<_> = contents.split('=').collect();
-        if line[0] != RTD_ROOT_VAR_NAME {
-            println!("You need to have {RTD_ROOT_VAR_NAME}=<absolute_path> in the config.");
+    let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    for (i, project) in review.projects.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        out.heading(project.path.display());
+        if project.open == 0 {
+            println!("No open tasks. Is it finished, or does it need a next action?");
+        }
+        let tasks = |title: &str, tasks: Vec<(&Task, String)>| {
+            if tasks.is_empty() {
+                return;
+            }
+            println!("{}:", title);
+            let width = style::id_width(tasks.iter().map(|(t, _)| *t));
+            for (task, note) in tasks {
+                println!("{}{}", out.line(task, width), note);
+            }
+        };
+        let waiting = project.waiting.iter().map(|t| (t, String::new()));
+        tasks("Waiting", waiting.collect());
+        let overdue = project.overdue.iter().map(|t| (t, String::new()));
+        tasks("Overdue", overdue.collect());
+        let stale = project
+            .stale
+            .iter()
+            .map(|(t, d)| (t, format!(" ({} days)", d)));
+        let max_days = if project.is_inbox() { inbox_days } else { days };
+        tasks(
+            &format!("Untouched for {}+ days", max_days),
+            stale.collect(),
+        );
+        if project.open > 0 && !project.needs_attention() {
+            println!("{} open tasks, all fine.", project.open);
         }
-        let rtd_root = line[1].strip_suffix('\n').expect("");
+        if interactive && i + 1 < review.projects.len() {
+            print!("Enter for the next project, q to stop: ");
+            let _ = std::io::stdout().flush();
+            let mut answer = String::new();
+            if std::io::stdin().read_line(&mut answer).unwrap_or(0) == 0 || answer.trim() == "q" {
+                return false;
+            }
+        }
+    }
 
-        let root_path = Path::new(rtd_root);
-        let inbox_path = root_path.join(INBOX_FNAME);
-        if !inbox_path.exists() {
-            println!("There is no {INBOX_FNAME} file in the root. Creating...");
-            let mut f = fs::File::create(inbox_path.clone())?;
-            f.write_all("".as_bytes())?;
+    let count = |f: fn(&ProjectReview) -> usize| review.projects.iter().map(f).sum::<usize>();
+    let empty: Vec<String> = review
+        .projects
+        .iter()
+        .filter(|p| p.open == 0)
+        .map(|p| p.path.display().to_string())
+        .collect();
+    println!();
+    out.heading("Summary");
+    match empty.len() {
+        0 => println!("{} projects, all with open tasks", review.projects.len()),
+        n => println!(
+            "{} projects, {} without open tasks: {}",
+            review.projects.len(),
+            n,
+            empty.join(", ")
+        ),
+    }
+    println!("Waiting: {}", count(|p| p.waiting.len()));
+    println!("Overdue: {}", count(|p| p.overdue.len()));
+    let stale = |inbox: bool| {
+        review
+            .projects
+            .iter()
+            .filter(|p| p.is_inbox() == inbox)
+            .map(|p| p.stale.len())
+            .sum::<usize>()
+    };
+    println!("Untouched for {}+ days: {}", days, stale(false));
+    println!("In the inbox for {}+ days: {}", inbox_days, stale(true));
+    match &review.last {
+        Some(last) => {
+            let ago = (to_naive(&out.today) - to_naive(last)).num_days();
+            println!("The last review was on {}, {} days ago.", last, ago);
         }
-        let root_stats = initialise(root_path);
-        // Initialisation ends
+        None => println!("This is your first review."),
+    }
+    true
+}
 
-        let args = Cli::parse();
-        match args.command {
-            Some(subcommand) => match subcommand {
-                SubcommandEnum::All => {
-                    for fpath in get_all_files(root_path) {
-                        show_file_tasks(&fpath, false, None);
-                    }
+fn show_by_id(out: &Output, task_id: &TaskId) -> rtd::Result<()> {
+    let (path, task) = out.ws.get_task_with_path(task_id)?;
+    show_task(out, &path, &task);
+    Ok(())
+}
+
+/// Opens the task line in $VISUAL or $EDITOR (vi if neither is set) and
+/// returns the edited line, None if it didn't change.
+fn edit_line(task_id: &TaskId, line: &str) -> rtd::Result<Option<String>> {
+    let path = std::env::temp_dir().join(format!("rtd-{}-{}.md", task_id, std::process::id()));
+    let io = |path: &Path, source| rtd::Error::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    fs::write(&path, format!("{}\n", line)).map_err(|e| io(&path, e))?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Editors are often set with arguments, e.g. "code --wait".
+    let mut words = editor.split_whitespace();
+    let status = Command::new(words.next().unwrap_or("vi"))
+        .args(words)
+        .arg(&path)
+        .status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    if !status.map_err(|e| io(Path::new(&editor), e))?.success() {
+        return Err(rtd::Error::Config(format!(
+            "The editor '{}' failed, the task is not changed.",
+            editor
+        )));
+    }
+    let edited = edited.map_err(|e| io(&path, e))?;
+    let text = edited.lines().map(str::trim).find(|l| !l.is_empty());
+    Ok(text.filter(|t| *t != line).map(str::to_string))
+}
+
+/// Sets up the workspace before a command that changes it.
+fn prepare(out: &Output) -> rtd::Result<Vec<IdChange>> {
+    let ws = out.ws;
+    for created in ws.ensure_layout()? {
+        out.message(&format!(
+            "There is no {} file in the root. Creating...",
+            ws.relative(&created).display()
+        ));
+    }
+    let changes = ws.assign_ids()?;
+    for change in changes.iter() {
+        let fpath = ws.relative(&change.path).display();
+        out.message(&match &change.old {
+            Some(old) => format!(
+                "Task &{} in {} had the same id as another task, it is &{} now: {}",
+                old,
+                fpath,
+                change.task.id().expect("The task has just got an id."),
+                change.task
+            ),
+            None => format!("New id in {}: {}", fpath, change.task),
+        });
+    }
+    Ok(changes)
+}
+
+fn run(out: &Output, config: &Config, args: Cli, id_changes: Vec<IdChange>) -> rtd::Result<()> {
+    let ws = out.ws;
+    let root = Path::new("");
+    let filter = Filter {
+        status: args.status,
+        min_priority: args.min_priority,
+        deferred: (!args.all).then_some(false),
+        ..Default::default()
+    };
+    match args.command {
+        Some(subcommand) => match subcommand {
+            SubcommandEnum::All => show(out, root, &filter)?,
+            SubcommandEnum::Inbox => {
+                if ws.inbox_path().exists() {
+                    show(out, Path::new(INBOX_FNAME), &filter)?
                 }
-                SubcommandEnum::Inbox => {
-                    show_file_tasks(&inbox_path, false, None);
+            }
+            SubcommandEnum::Query { query } => {
+                let filter = Filter {
+                    query: Some(Query::parse(&query.join(" "), &today())?),
+                    ..filter.clone()
+                };
+                show(out, root, &filter)?
+            }
+            SubcommandEnum::Search {
+                pattern,
+                regex,
+                archive,
+            } => {
+                let source = match regex {
+                    true => pattern,
+                    false => format!("(?i){}", regex::escape(&pattern)),
+                };
+                let re =
+                    Regex::new(&source).map_err(|e| rtd::Error::InvalidPattern(e.to_string()))?;
+                // Tasks that start later are found too.
+                let filter = Filter {
+                    deferred: None,
+                    ..filter.clone()
+                };
+                show_matches(out, &ws.search(&re, &filter, archive)?, &re);
+            }
+            SubcommandEnum::ShowView { name } => {
+                let view = config
+                    .views
+                    .iter()
+                    .find(|v| v.name == name)
+                    .ok_or_else(|| rtd::Error::Config(format!("There is no view '{}'.", name)))?;
+                show_view(out, view, &filter)?
+            }
+            SubcommandEnum::Views if !out.is_plain() => {
+                let records: Vec<Value> = config
+                    .views
+                    .iter()
+                    .map(|v| {
+                        json!({
+                            "name": v.name,
+                            "query": v.query,
+                            "sort": v.sort.to_string(),
+                            "group": v.group.to_string(),
+                            "format": v.format,
+                        })
+                    })
+                    .collect();
+                let columns = ["name", "query", "sort", "group", "format"];
+                print!("{}", render(out.format, &columns, &records));
+            }
+            SubcommandEnum::Views => {
+                if config.views.is_empty() {
+                    println!("No views yet, add one to ~/.rtd: view.today = due<=today");
                 }
-                SubcommandEnum::Due => {
-                    for fpath in get_all_files(root_path) {
-                        show_file_tasks(&fpath, true, None);
+                let width = config.views.iter().map(|v| v.name.len()).max().unwrap_or(0);
+                for view in config.views.iter() {
+                    let query = match view.query.trim() {
+                        "" => "all tasks",
+                        q => q,
+                    };
+                    match view.options().as_str() {
+                        "" => println!("  {:width$}  {}", view.name, query),
+                        options => println!("  {:width$}  {} ({})", view.name, query, options),
                     }
                 }
-                SubcommandEnum::Archive => {
-                    archive_tasks(root_path);
+            }
+            SubcommandEnum::Agenda { days } => show_agenda(out, &filter, days)?,
+            SubcommandEnum::Tickler => {
+                let filter = Filter {
+                    deferred: Some(true),
+                    ..filter.clone()
+                };
+                let mut projects = ws.query(root, &filter)?;
+                for project in projects.iter_mut() {
+                    project.tasks.sort_by_key(|t| t.start().map(to_naive));
+                }
+                out.projects(&projects);
+            }
+            SubcommandEnum::Due { within } => {
+                let filter = match within {
+                    Some(within) => Filter {
+                        due_before: Some(now() + within),
+                        ..filter.clone()
+                    },
+                    None => Filter {
+                        due_only: true,
+                        ..filter.clone()
+                    },
+                };
+                show(out, root, &filter)?
+            }
+            SubcommandEnum::Archive => {
+                let archived = ws.archive()?;
+                if out.is_plain() {
                     println!("All tasks archived (moved to .done)");
+                } else {
+                    out.projects(&archived);
                 }
-                SubcommandEnum::Labels => {
-                    let mut all_labels: HashSet<String> = HashSet::new();
-                    for fpath in get_all_files(root_path) {
-                        all_labels.extend(get_file_labels(&fpath));
-                    }
-                    for l in all_labels {
-                        println!("{l}");
+            }
+            SubcommandEnum::Labels { action: None } => {
+                let labels = ws.labels()?;
+                if out.is_plain() {
+                    let width = labels.iter().map(|(l, _)| l.chars().count()).max();
+                    for (label, count) in labels.iter() {
+                        println!("{:<width$} {}", label, count, width = width.unwrap_or(0));
                     }
+                } else {
+                    let records: Vec<Value> = labels
+                        .iter()
+                        .map(|(label, count)| json!({"label": label, "count": count}))
+                        .collect();
+                    print!("{}", render(out.format, &["label", "count"], &records));
                 }
-                SubcommandEnum::List => {
-                    let root_path_str = root_path.to_str().unwrap().to_string();
-                    for fpath in get_all_files(root_path) {
-                        println!(
-                            "{}",
-                            fpath
-                                .to_str()
-                                .expect("")
-                                .strip_prefix(&root_path_str)
-                                .unwrap()
-                        );
+            }
+            SubcommandEnum::Labels {
+                action: Some(action),
+            } => {
+                let (old, new, dry_run) = match &action {
+                    LabelAction::Rename { old, new, dry_run } => {
+                        (old, Some(new.as_str()), *dry_run)
                     }
+                    LabelAction::Delete { label, dry_run } => (label, None, *dry_run),
+                };
+                let changed = ws.rewrite_label(old, new, dry_run)?;
+                let count: usize = changed.iter().map(|p| p.tasks.len()).sum();
+                let what = match new {
+                    Some(new) => format!("{} renamed to {}", old, new),
+                    None => format!("{} removed", old),
+                };
+                match (count, dry_run) {
+                    (0, _) => out.message(&format!("No task has {}.", old)),
+                    (1, true) => out.message(&format!("1 task would have {}:", what)),
+                    (1, false) => out.message(&format!("1 task has {}:", what)),
+                    (n, true) => out.message(&format!("{} tasks would have {}:", n, what)),
+                    (n, false) => out.message(&format!("{} tasks have {}:", n, what)),
                 }
-                SubcommandEnum::Url { task_id } => {
-                    let task = get_task(task_id, root_path);
-                    if task.is_some() {
-                        let re = Regex::new(r"http://\S+|https://\S+").unwrap();
-                        for cap in re.captures_iter(&task.unwrap().to_string()) {
-                            println!("{}", &cap[0]);
-                        }
-                    }
+                out.projects(&changed);
+            }
+            SubcommandEnum::List => out.strings(
+                "project",
+                ws.files()?
+                    .iter()
+                    .map(|f| ws.relative(f).display().to_string()),
+            ),
+            SubcommandEnum::Url { task_id } => {
+                out.strings("url", ws.get_task(&task_id)?.urls().into_iter())
+            }
+            SubcommandEnum::Rm { task_id } => {
+                let removed = ws.remove_task(&task_id)?;
+                out.modified(&removed);
+                if out.is_plain() {
+                    out.task(&removed.task);
+                    println!("Task &{} is removed from the list", task_id);
                 }
-                SubcommandEnum::Rm { task_id } => remove_task(task_id, root_path),
-                SubcommandEnum::Toggle { task_id } => {
-                    modify_task(task_id, root_path, None, true, false)
+            }
+            SubcommandEnum::Toggle { task_id, subtasks } => {
+                let modification = Modification::ToggleStatus {
+                    with_subtasks: subtasks,
+                };
+                let modified = ws.modify_task(&task_id, &modification)?;
+                out.modified(&modified);
+                if out.is_plain() {
+                    println!("Changed status of the task {}", task_id);
+                    println!("Current state:");
+                    out.task(&modified.task);
+                    show_next(out, &modified);
                 }
-                SubcommandEnum::ToggleDate { task_id } => {
-                    modify_task(task_id, root_path, None, false, true)
+            }
+            SubcommandEnum::Status {
+                task_id,
+                status,
+                subtasks,
+            } => {
+                let modification = Modification::SetStatus {
+                    status,
+                    with_subtasks: subtasks,
+                };
+                let modified = ws.modify_task(&task_id, &modification)?;
+                out.modified(&modified);
+                if out.is_plain() {
+                    println!("Task &{} is {} now:", task_id, status);
+                    out.task(&modified.task);
+                    show_next(out, &modified);
                 }
-                SubcommandEnum::Add {
-                    task_description,
-                    project,
-                } => {
-                    let project_path = match project {
-                        Some(project) => root_path.join(&project),
-                        None => root_path.join(&inbox_path),
-                    };
-                    add_task(&task_description, &project_path, root_stats);
+            }
+            SubcommandEnum::Prio { task_id, level } => {
+                let priority = match level.as_str() {
+                    "none" | "0" => None,
+                    l => Some(l.parse().map_err(|_| rtd::Error::InvalidPriority(level))?),
+                };
+                let modified = ws.modify_task(&task_id, &Modification::SetPriority(priority))?;
+                out.modified(&modified);
+                if out.is_plain() {
+                    out.task(&modified.task);
                 }
-                SubcommandEnum::Mv { task_id, project } => {
-                    move_task(task_id, root_path, Path::new(&project))
+            }
+            SubcommandEnum::Date {
+                task_id,
+                when,
+                time,
+            } => {
+                let when = match time {
+                    Some(time) => format!("{}T{}", when, time),
+                    None => when,
+                };
+                let modification = Modification::set_date(&when, &today())?;
+                let modified = ws.modify_task(&task_id, &modification)?;
+                out.modified(&modified);
+                if out.is_plain() {
+                    out.task(&modified.task);
                 }
-                SubcommandEnum::AddLabel { task_id, label } => {
-                    if label.starts_with('@') {
-                        modify_task(task_id, root_path, Some(label), false, false);
-                    } else {
-                        eprintln!("A label should start with @ and have no spaces in it.");
-                    }
+            }
+            SubcommandEnum::ToggleDate { task_id } => {
+                out.modified(&ws.modify_task(&task_id, &Modification::ToggleDate)?);
+            }
+            SubcommandEnum::Add {
+                task_description,
+                project,
+            } => {
+                let project = project.unwrap_or_else(|| INBOX_FNAME.to_string());
+                let task = ws.add_task(&task_description, Path::new(&project))?;
+                out.changed(&ws.root().join(&project), &[&task]);
+                if out.is_plain() {
+                    println!("Added new task to {}:", project);
+                    out.task(&task);
                 }
-            },
-            None => match args.global_modifier {
-                Some(modifier) => {
-                    let maybe_path = root_path.join(modifier.clone());
-                    if let Ok(id) = modifier.parse::<i32>() {
-                        let task = get_task(id, root_path);
-                        if task.is_some() {
-                            println!("{}", &task.unwrap().to_string())
-                        }
-                    } else if modifier.starts_with('@') {
-                        for fpath in get_all_files(root_path) {
-                            show_file_tasks(&fpath, false, Some(modifier.clone()));
-                        }
-                    //TODO: Check files for keywords and throw an error
-                    // if there are folders with names due/labels etc.
-                    } else if maybe_path.exists() {
-                        // When we are here, we either get a folder name, or a file name.
-                        if maybe_path.clone().to_str().unwrap().ends_with(".md") {
-                            show_file_tasks(&maybe_path, false, None);
-                        } else {
-                            for fpath in get_all_files(&maybe_path) {
-                                show_file_tasks(&fpath, false, None);
-                            }
+            }
+            SubcommandEnum::Mv { task_id, project } => {
+                let moved = ws.move_task(&task_id, Path::new(&project))?;
+                out.modified(&moved);
+                if out.is_plain() {
+                    out.task(&moved.task);
+                    println!("Task &{} is moved to the list {}", task_id, project);
+                }
+            }
+            SubcommandEnum::AddLabel { task_id, label } => {
+                out.modified(&ws.modify_task(&task_id, &Modification::AddLabel(label))?);
+            }
+            SubcommandEnum::RemoveLabel { task_id, label } => {
+                let modified = ws.modify_task(&task_id, &Modification::RemoveLabel(label))?;
+                out.modified(&modified);
+                if out.is_plain() {
+                    out.task(&modified.task);
+                }
+            }
+            // Ids were assigned before running the command.
+            SubcommandEnum::Edit {
+                task_id,
+                title,
+                date,
+                clear_date,
+                add_label,
+                rm_label,
+            } => {
+                let mut modifications = Vec::new();
+                if let Some(title) = title {
+                    modifications.push(Modification::SetTitle(title));
+                }
+                if let Some(when) = date {
+                    modifications.push(Modification::set_date(&when, &today())?);
+                }
+                if clear_date {
+                    modifications.push(Modification::SetDate {
+                        date: None,
+                        time: None,
+                    });
+                }
+                modifications.extend(add_label.into_iter().map(Modification::AddLabel));
+                modifications.extend(rm_label.into_iter().map(Modification::RemoveLabel));
+                if modifications.is_empty() {
+                    let task = ws.get_task(&task_id)?;
+                    match edit_line(&task_id, task.line().trim())? {
+                        Some(text) => modifications.push(Modification::SetText(text)),
+                        None => {
+                            out.message("Nothing changed.");
+                            return Ok(());
                         }
-                    } else {
-                        println!("Unknown modifier: {}", modifier);
                     }
                 }
-                None => {
-                    for fpath in get_all_files(root_path) {
-                        show_file_tasks(&fpath, false, None);
+                let changes: Vec<(TaskId, Change)> = modifications
+                    .into_iter()
+                    .map(|m| (task_id.clone(), Change::Modify(m)))
+                    .collect();
+                let modified = ws.apply(&changes)?;
+                let last = modified
+                    .last()
+                    .expect("There is a change for every modification.");
+                out.modified(last);
+                if out.is_plain() {
+                    out.task(&last.task);
+                    for m in modified.iter() {
+                        show_next(out, m);
                     }
                 }
-            },
-        }
-    } else {
-        println!("You need to create a config at ~/{CONFIG_FNAME} and add GTD_DIR=<rtd_root_dir_absolute_path> there.");
+            }
+            SubcommandEnum::Tui => tui::run(ws, filter)?,
+            SubcommandEnum::Review { days, inbox_days } => {
+                let review = Review::new(ws, days, inbox_days, &today())?;
+                if show_review(out, &review, days, inbox_days) {
+                    review.finish(ws)?;
+                }
+            }
+            SubcommandEnum::Process => {
+                let mut input = std::io::stdin().lock();
+                let modified = if out.is_plain() {
+                    process::run(ws, &filter, &mut input, &mut std::io::stdout())?
+                } else {
+                    process::run(ws, &filter, &mut input, &mut std::io::stderr())?
+                };
+                for m in modified.iter() {
+                    out.modified(m);
+                }
+                if out.is_plain() && !modified.is_empty() {
+                    println!("Done, {} changes are written.", modified.len());
+                }
+            }
+            SubcommandEnum::Ids => {
+                if !out.is_plain() {
+                    let records: Vec<Value> = id_changes
+                        .iter()
+                        .map(|c| out.record(&c.path, &c.task))
+                        .collect();
+                    out.records(&records);
+                }
+            }
+        },
+        None => match args.global_modifier {
+            Some(modifier) => {
+                let maybe_path = ws.root().join(&modifier);
+                if let Ok(id @ TaskId::Number(_)) = modifier.parse::<TaskId>() {
+                    show_by_id(out, &id)?;
+                } else if is_valid_label(&modifier) {
+                    let filter = Filter {
+                        label: Some(modifier),
+                        ..filter.clone()
+                    };
+                    show(out, root, &filter)?;
+                //TODO: Check files for keywords and throw an error
+                // if there are folders with names due/labels etc.
+                } else if maybe_path.exists() {
+                    // When we are here, we either get a folder name, or a file name.
+                    show(out, Path::new(&modifier), &filter)?;
+                } else if let Ok(id) = modifier.parse::<TaskId>() {
+                    show_by_id(out, &id)?;
+                } else {
+                    let query = Query::parse(&modifier, &today())?;
+                    // A lone word is more likely a typo in a project than a query.
+                    if matches!(query, Query::Text(_)) && !modifier.trim_start().starts_with('"') {
+                        return Err(rtd::Error::UnknownModifier(modifier));
+                    }
+                    let filter = Filter {
+                        query: Some(query),
+                        ..filter.clone()
+                    };
+                    show(out, root, &filter)?;
+                }
+            }
+            None => show(out, root, &filter)?,
+        },
     }
     Ok(())
 }
+
+fn main() -> ExitCode {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let args = Cli::parse();
+    let result = Workspace::open(&config.root).and_then(|ws| {
+        let ws = ws
+            .with_id_mode(config.id_mode)
+            .with_archive_cancelled(config.archive_cancelled);
+        let out = Output {
+            ws: &ws,
+            format: args.format,
+            color: args.color.enabled(),
+            today: today(),
+        };
+        let mut id_changes = Vec::new();
+        if args.command.as_ref().is_some_and(SubcommandEnum::writes) {
+            id_changes = prepare(&out)?;
+        }
+        run(&out, &config, args, id_changes)
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
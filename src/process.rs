@@ -0,0 +1,430 @@
+//! `rtd process`, the clarify step of GTD: go through the inbox one task at a
+//! time and decide what each one is.
+//!
+//! Nothing is written while you decide. The decisions are applied together at
+//! the end with [`Workspace::apply`], so the files either get all of them or
+//! none.
+
+use crate::dates::today;
+use crate::error::{Error, Result};
+use crate::id::TaskId;
+use crate::task::{is_valid_label, sort_tasks, Task, TaskStatus};
+use crate::workspace::{Change, Filter, Modification, Modified, Workspace, INBOX_FNAME};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const ACTIONS: &str = "(m)ove, (l)abel, (d)ate, (x) done, (r)emove, (s)kip, (q)uit";
+/// How many matching projects to offer when the name is ambiguous.
+const CANDIDATES: usize = 9;
+
+/// Walks the open inbox tasks matching `filter`, asks what to do with each
+/// one and applies the decisions if the user confirms them. Prompts go to
+/// `output`, answers come from `input`. Returns the changed tasks.
+pub fn run(
+    ws: &Workspace,
+    filter: &Filter,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Vec<Modified>> {
+    let tasks = inbox(ws, filter)?;
+    let projects: Vec<PathBuf> = ws
+        .files()?
+        .iter()
+        .map(|f| ws.relative(f).to_path_buf())
+        .filter(|p| p != Path::new(INBOX_FNAME))
+        .collect();
+    let changes = decide(tasks, &projects, input, output).map_err(Error::Terminal)?;
+    if changes.is_empty() {
+        return Ok(Vec::new());
+    }
+    ws.apply(&changes)
+}
+
+/// Asks about every task, returns the changes once they are confirmed.
+fn decide(
+    tasks: Vec<Task>,
+    projects: &[PathBuf],
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<Vec<(TaskId, Change)>> {
+    if tasks.is_empty() {
+        writeln!(output, "The inbox is empty.")?;
+        return Ok(Vec::new());
+    }
+    writeln!(
+        output,
+        "{} tasks in the inbox. Nothing is written until the end.",
+        tasks.len()
+    )?;
+
+    let mut changes: Vec<(TaskId, Change)> = Vec::new();
+    let mut summary: Vec<String> = Vec::new();
+    'tasks: for (n, mut task) in tasks.into_iter().enumerate() {
+        let id = task.id().expect("Inbox tasks have ids.").clone();
+        writeln!(output)?;
+        loop {
+            writeln!(output, "[{}] {}", n + 1, task.line().trim())?;
+            let Some(action) = ask(input, output, ACTIONS)? else {
+                break 'tasks;
+            };
+            match action.as_str() {
+                "m" | "move" => {
+                    let Some(project) = pick_project(projects, input, output)? else {
+                        continue;
+                    };
+                    summary.push(format!("&{}: move to {}", id, project.display()));
+                    changes.push((id, Change::Move(project)));
+                    break;
+                }
+                "l" | "label" => {
+                    let Some(answer) = ask(input, output, "Labels")? else {
+                        continue;
+                    };
+                    let labels: Vec<String> = answer
+                        .split_whitespace()
+                        .map(|l| {
+                            if l.starts_with('@') {
+                                l.to_string()
+                            } else {
+                                format!("@{}", l)
+                            }
+                        })
+                        .collect();
+                    if let Some(bad) = labels.iter().find(|l| !is_valid_label(l)) {
+                        writeln!(output, "{}", Error::InvalidLabel(bad.clone()))?;
+                        continue;
+                    }
+                    for label in labels {
+                        task.add_label(&label);
+                        summary.push(format!("&{}: add {}", id, label));
+                        changes.push((id.clone(), Change::Modify(Modification::AddLabel(label))));
+                    }
+                }
+                "d" | "date" => {
+                    let Some(when) = ask(input, output, "Date (e.g. fri, +3d, none)")? else {
+                        continue;
+                    };
+                    match Modification::set_date(&when, &today()) {
+                        Ok(modification) => {
+                            if let Modification::SetDate { date, time } = &modification {
+                                task.set_date(date.clone());
+                                if time.is_some() {
+                                    task.set_time(*time);
+                                }
+                            }
+                            summary.push(format!("&{}: date {}", id, when));
+                            changes.push((id.clone(), Change::Modify(modification)));
+                        }
+                        Err(e) => writeln!(output, "{}", e)?,
+                    }
+                }
+                "x" | "done" => {
+                    let done = Modification::SetStatus {
+                        status: TaskStatus::Done,
+                        with_subtasks: false,
+                    };
+                    summary.push(format!("&{}: done", id));
+                    changes.push((id, Change::Modify(done)));
+                    break;
+                }
+                "r" | "remove" => {
+                    summary.push(format!("&{}: remove", id));
+                    changes.push((id, Change::Remove));
+                    break;
+                }
+                "" | "s" | "skip" => break,
+                "q" | "quit" => break 'tasks,
+                other => writeln!(output, "Unknown action '{}'.", other)?,
+            }
+        }
+    }
+
+    writeln!(output)?;
+    if changes.is_empty() {
+        writeln!(output, "Nothing to change.")?;
+        return Ok(changes);
+    }
+    for line in summary.iter() {
+        writeln!(output, "{}", line)?;
+    }
+    let question = format!("Apply {} changes? [y/N]", changes.len());
+    match ask(input, output, &question)?.as_deref() {
+        Some("y" | "yes") => Ok(changes),
+        _ => {
+            writeln!(output, "Nothing is changed.")?;
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// The open top level tasks of the inbox, in the order `rtd inbox` shows them.
+/// Subtasks go wherever their parents go.
+fn inbox(ws: &Workspace, filter: &Filter) -> Result<Vec<Task>> {
+    if !ws.inbox_path().exists() {
+        return Ok(Vec::new());
+    }
+    let mut tasks: Vec<Task> = ws
+        .query(Path::new(INBOX_FNAME), filter)?
+        .into_iter()
+        .flat_map(|p| p.tasks)
+        .collect();
+    sort_tasks(&mut tasks);
+    tasks.retain(|t| !t.status().is_closed() && t.parent().is_none() && t.id().is_some());
+    Ok(tasks)
+}
+
+/// Prints the question and reads the answer. None when the input has ended.
+fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+) -> io::Result<Option<String>> {
+    write!(output, "{}: ", question)?;
+    output.flush()?;
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        writeln!(output)?;
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+/// Asks for a project until the answer matches exactly one, or a number picks
+/// one of the offered ones. An empty answer gives up.
+fn pick_project(
+    projects: &[PathBuf],
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<Option<PathBuf>> {
+    let mut offered: Vec<&PathBuf> = Vec::new();
+    loop {
+        let question = if offered.is_empty() {
+            "Project"
+        } else {
+            "Pick a number or type again"
+        };
+        let Some(answer) = ask(input, output, question)? else {
+            return Ok(None);
+        };
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        if let Some(picked) = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| offered.get(n.wrapping_sub(1)))
+        {
+            return Ok(Some(picked.to_path_buf()));
+        }
+        offered = complete(projects, &answer);
+        match offered.as_slice() {
+            [] => writeln!(output, "No project matches '{}'.", answer)?,
+            [project] => {
+                writeln!(output, "{}", project.display())?;
+                return Ok(Some(project.to_path_buf()));
+            }
+            _ => {
+                for (i, project) in offered.iter().enumerate() {
+                    writeln!(output, "{}) {}", i + 1, project.display())?;
+                }
+            }
+        }
+    }
+}
+
+/// The projects matching `pattern`, the best first. A project named exactly
+/// like that is the only match.
+pub fn complete<'a>(projects: &'a [PathBuf], pattern: &str) -> Vec<&'a PathBuf> {
+    if let Some(exact) = projects.iter().find(|p| p.as_os_str() == pattern) {
+        return vec![exact];
+    }
+    let mut scored: Vec<(i64, &PathBuf)> = projects
+        .iter()
+        .filter_map(|p| Some((fuzzy_score(pattern, &p.to_string_lossy())?, p)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored
+        .into_iter()
+        .take(CANDIDATES)
+        .map(|(_, p)| p)
+        .collect()
+}
+
+/// How well `pattern` matches `candidate` when all its characters appear in it
+/// in the same order, ignoring case. Characters next to each other and at the
+/// start of a name count more, shorter candidates win ties. None if it doesn't
+/// match.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    for p in pattern.to_lowercase().chars() {
+        let i = next + candidate[next..].iter().position(|c| *c == p)?;
+        score += 1;
+        if i > 0 && last == Some(i - 1) {
+            score += 4;
+        }
+        if i == 0 || matches!(candidate[i - 1], '/' | '-' | '_' | ' ' | '.') {
+            score += 2;
+        }
+        last = Some(i);
+        next = i + 1;
+    }
+    Some(score * 100 - candidate.len() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::parse_task;
+    use crate::testing::TempRoot;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn tasks(lines: &[&str]) -> Vec<Task> {
+        lines.iter().map(|l| parse_task(l).unwrap()).collect()
+    }
+
+    /// Runs `decide` with the scripted answers, returns the changes in short
+    /// and what it printed.
+    fn script(tasks: Vec<Task>, projects: &[PathBuf], answers: &str) -> (Vec<String>, String) {
+        let mut output = Vec::new();
+        let changes = decide(tasks, projects, &mut answers.as_bytes(), &mut output).unwrap();
+        let changes = changes
+            .iter()
+            .map(|(id, change)| match change {
+                Change::Move(path) => format!("&{} move {}", id, path.display()),
+                Change::Remove => format!("&{} remove", id),
+                Change::Modify(Modification::AddLabel(label)) => format!("&{} {}", id, label),
+                Change::Modify(m) => format!("&{} {:?}", id, m),
+            })
+            .collect();
+        (changes, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn fuzzy_scores() {
+        assert_eq!(fuzzy_score("wrk", "work.md").map(|s| s > 0), Some(true));
+        assert_eq!(
+            fuzzy_score("WoRk", "work.md"),
+            fuzzy_score("work", "work.md")
+        );
+        assert_eq!(fuzzy_score("krow", "work.md"), None);
+        assert_eq!(fuzzy_score("works", "work.md"), None);
+        // Characters next to each other and at the start of names count more.
+        let score = |pattern, candidate| fuzzy_score(pattern, candidate).unwrap();
+        assert!(score("wo", "work.md") > score("wo", "w-door.md"));
+        assert!(score("h", "home/plan.md") > score("h", "chores.md"));
+        assert!(score("p", "work/plan.md") > score("p", "shopping.md"));
+        // Shorter ones win ties.
+        assert!(score("work", "work.md") > score("work", "work/a.md"));
+    }
+
+    #[test]
+    fn completes_project_names() {
+        let projects = paths(&["home.md", "work.md", "work/plan.md", "shopping.md"]);
+        let names = |pattern| -> Vec<String> {
+            complete(&projects, pattern)
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect()
+        };
+        assert_eq!(names("work.md"), ["work.md"]);
+        assert_eq!(names("wrk"), ["work.md", "work/plan.md"]);
+        assert_eq!(names("pl"), ["work/plan.md"]);
+        assert!(names("zzz").is_empty());
+        let many: Vec<PathBuf> = (1..=12)
+            .map(|i| PathBuf::from(format!("p{}.md", i)))
+            .collect();
+        assert_eq!(complete(&many, "p").len(), CANDIDATES);
+    }
+
+    #[test]
+    fn picks_projects() {
+        let projects = paths(&["home.md", "work.md", "work/plan.md"]);
+        let pick = |answers: &str| {
+            let mut output = Vec::new();
+            pick_project(&projects, &mut answers.as_bytes(), &mut output).unwrap()
+        };
+        assert_eq!(pick("home\n"), Some(PathBuf::from("home.md")));
+        // Several match, pick one by its number.
+        assert_eq!(pick("wrk\n2\n"), Some(PathBuf::from("work/plan.md")));
+        // A number that wasn't offered is a pattern like any other.
+        assert_eq!(pick("wrk\n7\nplan\n"), Some(PathBuf::from("work/plan.md")));
+        assert_eq!(pick("1\nhome\n"), Some(PathBuf::from("home.md")));
+        assert_eq!(pick("zzz\n\n"), None);
+        assert_eq!(pick(""), None);
+    }
+
+    #[test]
+    fn decides_about_every_task() {
+        let projects = paths(&["home.md", "work.md"]);
+        let inbox = tasks(&["- [ ] &1 A", "- [ ] &2 B", "- [ ] &3 C", "- [ ] &4 D"]);
+        // A bad label is asked again, labels get their @, a task can be
+        // labelled and then moved.
+        let answers = "l\n@ next\nl\nnext @home\nm\nhome\ns\nx\nr\ny\n";
+        let (changes, output) = script(inbox, &projects, answers);
+        assert_eq!(
+            changes,
+            [
+                "&1 @next",
+                "&1 @home",
+                "&1 move home.md",
+                "&3 SetStatus { status: Done, with_subtasks: false }",
+                "&4 remove",
+            ]
+        );
+        assert!(output.contains("A label should start with @"));
+        assert!(output.contains("&1: move to home.md\n"));
+        assert!(output.contains("Apply 5 changes? [y/N]"));
+    }
+
+    #[test]
+    fn changes_nothing_unless_confirmed() {
+        let projects = paths(&["work.md"]);
+        let inbox = || tasks(&["- [ ] &1 A", "- [ ] &2 B"]);
+        for answers in ["x\nx\nn\n", "x\nx\n\n", "x\nx\n", "x\nq\nno\n"] {
+            let (changes, output) = script(inbox(), &projects, answers);
+            assert!(changes.is_empty(), "{:?}", answers);
+            assert!(output.ends_with("Nothing is changed.\n"), "{:?}", answers);
+        }
+        // Quitting keeps the decisions so far.
+        let (changes, _) = script(inbox(), &projects, "x\nq\ny\n");
+        assert_eq!(changes.len(), 1);
+        let (changes, output) = script(inbox(), &projects, "s\nfoo\n\n");
+        assert!(changes.is_empty());
+        assert!(output.contains("Unknown action 'foo'."));
+        assert!(output.ends_with("Nothing to change.\n"));
+    }
+
+    #[test]
+    fn applies_the_decisions_at_once() {
+        let root = TempRoot::new(&[
+            (
+                "inbox.md",
+                "- [ ] &1 A\n  - [ ] &2 Sub\n- [ ] &3 B !1\n- [x] &4 C\n",
+            ),
+            ("work.md", "- [ ] &5 D\n"),
+        ]);
+        let ws = Workspace::open(root.path()).unwrap();
+        let mut output = Vec::new();
+        // B comes first, it has a priority. Done and sub tasks are not asked about.
+        let answers = "l\nnext\ns\nm\nwork\ny\n";
+        let modified = run(
+            &ws,
+            &Filter::default(),
+            &mut answers.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(modified.len(), 2);
+        assert_eq!(root.read("inbox.md"), "- [ ] &3 B !1 @next\n- [x] &4 C\n");
+        assert_eq!(
+            root.read("work.md"),
+            "- [ ] &5 D\n- [ ] &1 A\n  - [ ] &2 Sub\n"
+        );
+    }
+}
@@ -0,0 +1,590 @@
+//! The query language of `rtd query`.
+//!
+//! ```text
+//! @next and not @waiting and due<=+7d and project:work/ and "invoice"
+//! ```
+//!
+//! - `@label` matches tasks with the label.
+//! - `"some text"` or a bare word matches tasks with it in the title, ignoring
+//!   case. Links are words too.
+//! - `project:work/` matches tasks in the folder, `project:home.md` in the file.
+//! - `status:done` matches tasks with the status.
+//! - `has:date`, `has:start`, `has:priority`, `has:recurrence` and `has:notes`.
+//! - `due`, `start` and `prio` compare with `<`, `<=`, `=`, `!=`, `>=` and `>`.
+//!   Dates can be relative, e.g. `due<today` or `start>=+1w`; tasks without a
+//!   date never match. `prio<=2` matches `!1` and `!2`.
+//! - `not`, `and`, `or` and parentheses. `and` binds tighter than `or`, and
+//!   two conditions next to each other are joined with `and`.
+
+use crate::dates::parse_date;
+use crate::error::{Error, Result};
+use crate::task::{Priority, Task, TaskStatus};
+use speedate::Date;
+use std::cmp::Ordering;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    // Longer operators go first, so that `<=` isn't taken for `<`.
+    const OPERATORS: [(&'static str, Comparison); 7] = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("!=", Comparison::NotEqual),
+        ("==", Comparison::Equal),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+        ("=", Comparison::Equal),
+    ];
+
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Less => ordering.is_lt(),
+            Comparison::LessOrEqual => ordering.is_le(),
+            Comparison::Equal => ordering.is_eq(),
+            Comparison::NotEqual => ordering.is_ne(),
+            Comparison::GreaterOrEqual => ordering.is_ge(),
+            Comparison::Greater => ordering.is_gt(),
+        }
+    }
+}
+
+/// What a task can have, for `has:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    Date,
+    Start,
+    Priority,
+    Recurrence,
+    Notes,
+}
+
+/// A parsed query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Label(String),
+    /// Lowercase text to look for in the title.
+    Text(String),
+    /// A path relative to the root, folders end with /.
+    Project(String),
+    Status(TaskStatus),
+    Has(Property),
+    Due(Comparison, Date),
+    Start(Comparison, Date),
+    Priority(Comparison, Priority),
+}
+
+impl Query {
+    /// Parses a query, relative dates are counted from `today`.
+    pub fn parse(query: &str, today: &Date) -> Result<Query> {
+        let tokens = lex(query)?;
+        let mut parser = Parser {
+            query,
+            tokens,
+            pos: 0,
+            today,
+        };
+        let parsed = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(parsed),
+            Some(t) => Err(parser.error(t.start, "expected 'and', 'or' or the end of the query")),
+        }
+    }
+
+    /// `path` is the path of the task's file relative to the root.
+    pub fn matches(&self, task: &Task, path: &Path) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(task, path) && b.matches(task, path),
+            Query::Or(a, b) => a.matches(task, path) || b.matches(task, path),
+            Query::Not(q) => !q.matches(task, path),
+            Query::Label(label) => task.has_label(label),
+            Query::Text(text) => task.title().to_lowercase().contains(text),
+            Query::Project(project) => {
+                let path = path.to_string_lossy();
+                match project.strip_suffix('/') {
+                    Some(dir) => path.starts_with(project) || dir.is_empty(),
+                    None => {
+                        path == project.as_str()
+                            || path
+                                .strip_prefix(project.as_str())
+                                .is_some_and(|rest| rest.starts_with('/') || rest == ".md")
+                    }
+                }
+            }
+            Query::Status(status) => task.status() == *status,
+            Query::Has(property) => match property {
+                Property::Date => task.date().is_some(),
+                Property::Start => task.start().is_some(),
+                Property::Priority => task.priority().is_some(),
+                Property::Recurrence => task.recurrence().is_some(),
+                Property::Notes => !task.notes().is_empty(),
+            },
+            Query::Due(cmp, date) => task
+                .date()
+                .is_some_and(|d| cmp.holds(compare_dates(d, date))),
+            Query::Start(cmp, date) => task
+                .start()
+                .is_some_and(|d| cmp.holds(compare_dates(d, date))),
+            Query::Priority(cmp, priority) => {
+                task.priority().is_some_and(|p| cmp.holds(p.cmp(priority)))
+            }
+        }
+    }
+}
+
+fn compare_dates(a: &Date, b: &Date) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    Word(String),
+    Quoted(String),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    /// Byte offset in the query, for errors.
+    start: usize,
+}
+
+fn lex(query: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::Open,
+            ')' => TokenKind::Close,
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => text.push(c),
+                        None => return Err(query_error(query, start, "the quote is never closed")),
+                    }
+                }
+                TokenKind::Quoted(text)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| !c.is_whitespace() && !matches!(c, '(' | ')' | '"'))
+                {
+                    word.push(c);
+                }
+                TokenKind::Word(word)
+            }
+        };
+        tokens.push(Token { kind, start });
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    today: &'a Date,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Result<Query> {
+        let mut left = self.and()?;
+        while self.eat_keyword("or") {
+            let right = self.and()?;
+            left = Query::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut left = self.unary()?;
+        // Two conditions next to each other are joined with `and` too.
+        while self.eat_keyword("and") || self.at_condition() {
+            let right = self.unary()?;
+            left = Query::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Query> {
+        if self.eat_keyword("not") {
+            return Ok(Query::Not(Box::new(self.unary()?)));
+        }
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(self.error(self.query.len(), "expected a condition"));
+        };
+        self.pos += 1;
+        match token.kind {
+            TokenKind::Open => {
+                let inner = self.or()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token {
+                        kind: TokenKind::Close,
+                        ..
+                    }) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    Some(t) => Err(self.error(t.start, "expected ')'")),
+                    None => Err(self.error(token.start, "the parenthesis is never closed")),
+                }
+            }
+            TokenKind::Close => Err(self.error(token.start, "unexpected ')'")),
+            TokenKind::Quoted(text) => Ok(Query::Text(text.to_lowercase())),
+            TokenKind::Word(word) => self.condition(&word, token.start),
+        }
+    }
+
+    fn condition(&self, word: &str, start: usize) -> Result<Query> {
+        if word.starts_with('@') {
+            return Ok(Query::Label(word.to_string()));
+        }
+        // A link, like https://example.org, is text and not a key.
+        if let Some((key, value)) = word
+            .split_once(':')
+            .filter(|(_, value)| !value.starts_with("//"))
+        {
+            return match key {
+                "project" => Ok(Query::Project(value.to_string())),
+                "status" => value
+                    .parse()
+                    .map(Query::Status)
+                    .map_err(|e: String| self.error(start, &e)),
+                "has" => {
+                    let property = match value {
+                        "date" | "due" => Property::Date,
+                        "start" => Property::Start,
+                        "priority" | "prio" => Property::Priority,
+                        "recurrence" | "every" => Property::Recurrence,
+                        "notes" => Property::Notes,
+                        _ => {
+                            return Err(self.error(
+                                start,
+                                "has: takes date, start, priority, recurrence or notes",
+                            ))
+                        }
+                    };
+                    Ok(Query::Has(property))
+                }
+                _ if key.chars().all(char::is_alphabetic) => Err(self.error(
+                    start,
+                    &format!("unknown key '{}', use project, status or has", key),
+                )),
+                _ => Ok(Query::Text(word.to_lowercase())),
+            };
+        }
+        let operator = word.char_indices().find_map(|(i, _)| {
+            Comparison::OPERATORS
+                .iter()
+                .find(|(op, _)| word[i..].starts_with(op))
+                .map(|(op, cmp)| (i, *op, *cmp))
+        });
+        let Some((at, op, cmp)) = operator else {
+            return Ok(Query::Text(word.to_lowercase()));
+        };
+        let (field, value) = (&word[..at], &word[at + op.len()..]);
+        let value_start = start + at + op.len();
+        match field {
+            "due" | "date" | "start" => {
+                let date = parse_date(value, self.today).ok_or_else(|| {
+                    self.error(
+                        value_start,
+                        &format!("can't understand the date '{}'", value),
+                    )
+                })?;
+                Ok(if field == "start" {
+                    Query::Start(cmp, date)
+                } else {
+                    Query::Due(cmp, date)
+                })
+            }
+            "prio" | "priority" => value
+                .parse()
+                .map(|p| Query::Priority(cmp, p))
+                .map_err(|e: String| self.error(value_start, &e)),
+            _ if field.chars().all(char::is_alphabetic) => Err(self.error(
+                start,
+                &format!("unknown field '{}', use due, start or prio", field),
+            )),
+            // Something like a=b in a link, it's just text.
+            _ => Ok(Query::Text(word.to_lowercase())),
+        }
+    }
+
+    fn at_condition(&self) -> bool {
+        match self.tokens.get(self.pos).map(|t| &t.kind) {
+            Some(TokenKind::Word(w)) => w != "or" && w != "and",
+            Some(TokenKind::Open | TokenKind::Quoted(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token {
+                kind: TokenKind::Word(w),
+                ..
+            }) if w == keyword => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn error(&self, at: usize, message: &str) -> Error {
+        query_error(self.query, at, message)
+    }
+}
+
+fn query_error(query: &str, at: usize, message: &str) -> Error {
+    Error::InvalidQuery {
+        query: query.to_string(),
+        at,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::parse_task;
+
+    fn date(s: &str) -> Date {
+        Date::parse_str_rfc3339(s).unwrap()
+    }
+
+    fn parse(query: &str) -> Query {
+        // A Thursday.
+        Query::parse(query, &date("2024-01-25")).unwrap()
+    }
+
+    /// The byte offset and the message of the error.
+    fn error(query: &str) -> (usize, String) {
+        match Query::parse(query, &date("2024-01-25")) {
+            Err(Error::InvalidQuery { at, message, .. }) => (at, message),
+            other => panic!("{} parsed to {:?}", query, other),
+        }
+    }
+
+    fn label(l: &str) -> Query {
+        Query::Label(l.to_string())
+    }
+
+    fn and(a: Query, b: Query) -> Query {
+        Query::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Query, b: Query) -> Query {
+        Query::Or(Box::new(a), Box::new(b))
+    }
+
+    fn not(q: Query) -> Query {
+        Query::Not(Box::new(q))
+    }
+
+    fn matches(query: &str, line: &str, path: &str) -> bool {
+        parse(query).matches(&parse_task(line).unwrap(), Path::new(path))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("@a or @b and @c"),
+            or(label("@a"), and(label("@b"), label("@c")))
+        );
+        assert_eq!(
+            parse("@a and @b or @c"),
+            or(and(label("@a"), label("@b")), label("@c"))
+        );
+        assert_eq!(
+            parse("@a or @b or @c"),
+            or(or(label("@a"), label("@b")), label("@c"))
+        );
+    }
+
+    #[test]
+    fn conditions_next_to_each_other_are_and() {
+        assert_eq!(parse("@a @b"), and(label("@a"), label("@b")));
+        assert_eq!(
+            parse("@a \"Some Text\" or @c"),
+            or(
+                and(label("@a"), Query::Text("some text".to_string())),
+                label("@c")
+            )
+        );
+        assert_eq!(parse("@a (@b)"), and(label("@a"), label("@b")));
+    }
+
+    #[test]
+    fn not_binds_to_the_next_condition() {
+        assert_eq!(parse("not @a and @b"), and(not(label("@a")), label("@b")));
+        assert_eq!(parse("not not @a"), not(not(label("@a"))));
+        assert_eq!(parse("not (@a or @b)"), not(or(label("@a"), label("@b"))));
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            parse("(@a or @b) and @c"),
+            and(or(label("@a"), label("@b")), label("@c"))
+        );
+        assert_eq!(parse("((@a))"), label("@a"));
+    }
+
+    #[test]
+    fn every_comparison() {
+        let two: Priority = "2".parse().unwrap();
+        for (op, cmp) in [
+            ("<", Comparison::Less),
+            ("<=", Comparison::LessOrEqual),
+            ("=", Comparison::Equal),
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            (">=", Comparison::GreaterOrEqual),
+            (">", Comparison::Greater),
+        ] {
+            assert_eq!(parse(&format!("prio{}2", op)), Query::Priority(cmp, two));
+            assert_eq!(
+                parse(&format!("due{}2024-02-01", op)),
+                Query::Due(cmp, date("2024-02-01"))
+            );
+        }
+        let matching = |query: &str| {
+            ["!1", "!2", "!3"]
+                .into_iter()
+                .filter(|p| matches(query, &format!("- [ ] &1 Task {}", p), "inbox.md"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matching("prio<2"), ["!1"]);
+        assert_eq!(matching("prio<=2"), ["!1", "!2"]);
+        assert_eq!(matching("prio=2"), ["!2"]);
+        assert_eq!(matching("prio!=2"), ["!1", "!3"]);
+        assert_eq!(matching("prio>=2"), ["!2", "!3"]);
+        assert_eq!(matching("prio>2"), ["!3"]);
+        assert_eq!(matching("prio<=B"), ["!1", "!2"]);
+        // Tasks without a priority never match.
+        assert!(!matches("prio>=1", "- [ ] &1 Task", "inbox.md"));
+        assert!(!matches("prio!=1", "- [ ] &1 Task", "inbox.md"));
+    }
+
+    #[test]
+    fn relative_dates() {
+        assert_eq!(
+            parse("due<=+7d"),
+            Query::Due(Comparison::LessOrEqual, date("2024-02-01"))
+        );
+        assert_eq!(
+            parse("date<today"),
+            Query::Due(Comparison::Less, date("2024-01-25"))
+        );
+        assert_eq!(
+            parse("start>=fri"),
+            Query::Start(Comparison::GreaterOrEqual, date("2024-01-26"))
+        );
+        assert!(matches("due<=+7d", "- [ ] &1 Task %2024-02-01", "inbox.md"));
+        assert!(!matches(
+            "due<=+7d",
+            "- [ ] &1 Task %2024-02-02",
+            "inbox.md"
+        ));
+        assert!(!matches("due<=+7d", "- [ ] &1 Task", "inbox.md"));
+        assert!(matches(
+            "start>today",
+            "- [ ] &1 Task ^2024-01-26",
+            "inbox.md"
+        ));
+    }
+
+    #[test]
+    fn projects_are_folders_or_files() {
+        let task = "- [ ] &1 Task";
+        assert!(matches("project:work/", task, "work/a.md"));
+        assert!(matches("project:work/", task, "work/deep/b.md"));
+        assert!(!matches("project:work/", task, "work.md"));
+        assert!(!matches("project:work/", task, "workshop/a.md"));
+        // Without the slash, the file or the folder.
+        assert!(matches("project:work", task, "work.md"));
+        assert!(matches("project:work", task, "work/a.md"));
+        assert!(!matches("project:work", task, "workshop.md"));
+        assert!(matches("project:home.md", task, "home.md"));
+        assert!(!matches("project:home.md", task, "work/home.md"));
+        assert!(matches("project:work/a.md", task, "work/a.md"));
+        assert!(matches("project:/", task, "anything.md"));
+    }
+
+    #[test]
+    fn other_conditions() {
+        assert!(matches("status:done", "- [x] &1 Task", "inbox.md"));
+        assert!(!matches("status:done", "- [ ] &1 Task", "inbox.md"));
+        assert!(matches("status:in-progress", "- [/] &1 Task", "inbox.md"));
+        assert!(matches("has:date", "- [ ] &1 Task %2024-01-01", "inbox.md"));
+        assert!(matches("has:every", "- [ ] &1 Task *every:1w", "inbox.md"));
+        assert!(!matches("has:priority", "- [ ] &1 Task", "inbox.md"));
+        assert!(matches(
+            "INVOICE",
+            "- [ ] &1 Pay the Invoice @bills",
+            "inbox.md"
+        ));
+        // Text is looked up in the title only.
+        assert!(!matches(
+            "bills",
+            "- [ ] &1 Pay the Invoice @bills",
+            "inbox.md"
+        ));
+    }
+
+    #[test]
+    fn links_are_text() {
+        assert_eq!(
+            parse("https://x.org"),
+            Query::Text("https://x.org".to_string())
+        );
+        assert_eq!(
+            parse("https://x.org/?a=b"),
+            Query::Text("https://x.org/?a=b".to_string())
+        );
+        assert!(matches(
+            "https://x.org/docs",
+            "- [ ] &1 Read https://x.org/docs",
+            "inbox.md"
+        ));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(error("@a and").0, 6);
+        assert_eq!(error("@a or ").0, 6);
+        assert_eq!(error("(@a or @b").0, 0);
+        assert_eq!(error("@a (@b @c").0, 3);
+        assert_eq!(error("@a )").0, 3);
+        assert_eq!(error(") @a").0, 0);
+        assert_eq!(error("@a \"text").0, 3);
+        assert_eq!(error("@a due<=someday").0, 8);
+        assert_eq!(error("@a prio>9").0, 8);
+        assert_eq!(error("@a status:finished").0, 3);
+        assert_eq!(error("@a has:colour").0, 3);
+        assert_eq!(error("@a foo:bar").0, 3);
+        assert_eq!(error("@a size>3").0, 3);
+        let (at, message) = error("@a   due<=someday");
+        assert_eq!(at, 10);
+        assert_eq!(message, "can't understand the date 'someday'");
+    }
+}
@@ -0,0 +1,291 @@
+//! Recurring tasks.
+//!
+//! A task with `*every:1w` comes back a week after its date when it's done,
+//! one with `*after:1w` comes back a week after the day it was done. The
+//! interval is a number with `d`, `w`, `m` or `y`, or one of `daily`, `weekly`,
+//! `monthly`, `yearly` and `weekday` (Monday to Friday).
+
+use crate::dates::{add_days, add_months, weekday};
+use chrono::Weekday;
+use speedate::Date;
+use std::fmt;
+use std::str::FromStr;
+
+const EVERY_PREFIX: &str = "*every:";
+const AFTER_PREFIX: &str = "*after:";
+/// The longest interval in its unit, anything longer ends past year 9999.
+const MAX_INTERVAL: u32 = 9999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+    /// The next day from Monday to Friday.
+    Weekday,
+}
+
+impl Interval {
+    /// None when the result is past year 9999.
+    fn add_to(&self, date: &Date) -> Option<Date> {
+        match *self {
+            Interval::Days(n) => add_days(date, n as i64),
+            Interval::Weeks(n) => add_days(date, (n as i64).checked_mul(7)?),
+            Interval::Months(n) => add_months(date, n),
+            Interval::Years(n) => add_months(date, n.checked_mul(12)?),
+            Interval::Weekday => {
+                let mut next = add_days(date, 1)?;
+                while matches!(weekday(&next), Weekday::Sat | Weekday::Sun) {
+                    next = add_days(&next, 1)?;
+                }
+                Some(next)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interval::Days(n) => write!(f, "{}d", n),
+            Interval::Weeks(n) => write!(f, "{}w", n),
+            Interval::Months(n) => write!(f, "{}m", n),
+            Interval::Years(n) => write!(f, "{}y", n),
+            Interval::Weekday => f.write_str("weekday"),
+        }
+    }
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Interval, String> {
+        let err = || {
+            format!(
+                "Unknown interval '{}', use e.g. 3d, 1w, 2m, 1y (at most {}), daily, weekly, monthly, yearly or weekday.",
+                s, MAX_INTERVAL
+            )
+        };
+        match s {
+            "daily" => return Ok(Interval::Days(1)),
+            "weekly" => return Ok(Interval::Weeks(1)),
+            "monthly" => return Ok(Interval::Months(1)),
+            "yearly" => return Ok(Interval::Years(1)),
+            "weekday" | "weekdays" => return Ok(Interval::Weekday),
+            _ => {}
+        }
+        let unit_at = s
+            .len()
+            .checked_sub(1)
+            .filter(|i| s.is_char_boundary(*i))
+            .ok_or_else(err)?;
+        let n: u32 = s[..unit_at].parse().map_err(|_| err())?;
+        if n == 0 || n > MAX_INTERVAL {
+            return Err(err());
+        }
+        match &s[unit_at..] {
+            "d" => Ok(Interval::Days(n)),
+            "w" => Ok(Interval::Weeks(n)),
+            "m" => Ok(Interval::Months(n)),
+            "y" => Ok(Interval::Years(n)),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// How often a task comes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recurrence {
+    pub interval: Interval,
+    /// Count from the day the task was done instead of its date.
+    pub from_completion: bool,
+}
+
+impl Recurrence {
+    /// Parses a recurrence token, `*every:1w` or `*after:1w`.
+    pub(crate) fn from_token(token: &str) -> Option<Recurrence> {
+        let (interval, from_completion) = match token.strip_prefix(EVERY_PREFIX) {
+            Some(interval) => (interval, false),
+            None => (token.strip_prefix(AFTER_PREFIX)?, true),
+        };
+        Some(Recurrence {
+            interval: interval.parse().ok()?,
+            from_completion,
+        })
+    }
+
+    /// The date of the next instance of a task with the date `due` that was
+    /// done on `done_on`. A task that is late skips the dates that have
+    /// already passed. None if the next date is past year 9999.
+    pub fn next(&self, due: Option<&Date>, done_on: &Date) -> Option<Date> {
+        let due = match due {
+            Some(due) if !self.from_completion => due,
+            _ => return self.interval.add_to(done_on),
+        };
+        let mut next = self.interval.add_to(due)?;
+        while next <= *done_on {
+            next = self.interval.add_to(&next)?;
+        }
+        Some(next)
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.from_completion {
+            AFTER_PREFIX
+        } else {
+            EVERY_PREFIX
+        };
+        write!(f, "{}{}", prefix, self.interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Date {
+        Date::parse_str_rfc3339(s).unwrap()
+    }
+
+    fn next(token: &str, due: Option<&str>, done_on: &str) -> Option<String> {
+        let recurrence = Recurrence::from_token(token).unwrap();
+        let due = due.map(date);
+        recurrence
+            .next(due.as_ref(), &date(done_on))
+            .map(|d| d.to_string())
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!("3d".parse(), Ok(Interval::Days(3)));
+        assert_eq!("2w".parse(), Ok(Interval::Weeks(2)));
+        assert_eq!("1m".parse(), Ok(Interval::Months(1)));
+        assert_eq!("9999y".parse(), Ok(Interval::Years(9999)));
+        assert_eq!("daily".parse(), Ok(Interval::Days(1)));
+        assert_eq!("weekly".parse(), Ok(Interval::Weeks(1)));
+        assert_eq!("monthly".parse(), Ok(Interval::Months(1)));
+        assert_eq!("yearly".parse(), Ok(Interval::Years(1)));
+        assert_eq!("weekdays".parse(), Ok(Interval::Weekday));
+        for bad in [
+            "",
+            "d",
+            "0d",
+            "-1d",
+            "1x",
+            "1",
+            "é",
+            "1é",
+            "10000d",
+            "99999999d",
+            "400000000y",
+        ] {
+            assert!(bad.parse::<Interval>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn intervals_round_trip() {
+        for token in ["*every:3d", "*every:2w", "*after:1m", "*after:weekday"] {
+            assert_eq!(Recurrence::from_token(token).unwrap().to_string(), token);
+        }
+        assert_eq!(
+            Recurrence::from_token("*every:daily").unwrap().to_string(),
+            "*every:1d"
+        );
+        assert_eq!(Recurrence::from_token("*often:1d"), None);
+    }
+
+    #[test]
+    fn counts_from_the_date() {
+        assert_eq!(
+            next("*every:1w", Some("2024-01-01"), "2024-01-01").as_deref(),
+            Some("2024-01-08")
+        );
+        // Done early, the date still moves from the old one.
+        assert_eq!(
+            next("*every:1m", Some("2024-03-15"), "2024-03-01").as_deref(),
+            Some("2024-04-15")
+        );
+        assert_eq!(
+            next("*every:1m", Some("2024-01-31"), "2024-01-31").as_deref(),
+            Some("2024-02-29")
+        );
+        assert_eq!(
+            next("*every:1y", Some("2024-02-29"), "2024-02-29").as_deref(),
+            Some("2025-02-28")
+        );
+    }
+
+    #[test]
+    fn late_tasks_skip_past_dates() {
+        assert_eq!(
+            next("*every:1w", Some("2024-01-01"), "2024-01-20").as_deref(),
+            Some("2024-01-22")
+        );
+        // A date on the day it's done has passed too.
+        assert_eq!(
+            next("*every:1w", Some("2024-01-01"), "2024-01-22").as_deref(),
+            Some("2024-01-29")
+        );
+        assert_eq!(
+            next("*every:1d", Some("2000-01-01"), "2024-01-20").as_deref(),
+            Some("2024-01-21")
+        );
+    }
+
+    #[test]
+    fn counts_from_completion() {
+        assert_eq!(
+            next("*after:1w", Some("2024-01-01"), "2024-01-20").as_deref(),
+            Some("2024-01-27")
+        );
+        assert_eq!(
+            next("*after:3d", Some("2024-02-01"), "2024-01-20").as_deref(),
+            Some("2024-01-23")
+        );
+        // Without a date every counts from the day it's done as well.
+        assert_eq!(
+            next("*every:3d", None, "2024-01-20").as_deref(),
+            Some("2024-01-23")
+        );
+    }
+
+    #[test]
+    fn weekday_skips_the_weekend() {
+        // 2024-01-26 is a Friday.
+        assert_eq!(
+            next("*every:weekday", Some("2024-01-26"), "2024-01-26").as_deref(),
+            Some("2024-01-29")
+        );
+        assert_eq!(
+            next("*after:weekday", None, "2024-01-27").as_deref(),
+            Some("2024-01-29")
+        );
+        assert_eq!(
+            next("*after:weekday", None, "2024-01-23").as_deref(),
+            Some("2024-01-24")
+        );
+    }
+
+    #[test]
+    fn no_date_past_year_9999() {
+        assert_eq!(next("*every:9999y", Some("2024-01-01"), "2024-01-01"), None);
+        assert_eq!(next("*every:9999d", Some("9999-12-01"), "9999-12-01"), None);
+        assert_eq!(next("*after:weekday", None, "9999-12-31"), None);
+        for interval in [
+            Interval::Days(u32::MAX),
+            Interval::Weeks(u32::MAX),
+            Interval::Months(u32::MAX),
+            Interval::Years(u32::MAX),
+        ] {
+            let recurrence = Recurrence {
+                interval,
+                from_completion: true,
+            };
+            assert_eq!(recurrence.next(None, &date("2024-01-01")), None);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! `rtd review`, the weekly review of GTD: go through every project and see
+//! what needs attention.
+//!
+//! A project needs attention when it has no open tasks (it's finished or
+//! stuck), and a task when it waits for someone (`@waiting`), is overdue, or
+//! nobody touched it for a while. For the inbox, a while is shorter.
+
+use crate::dates::to_naive;
+use crate::error::Result;
+use crate::history::History;
+use crate::id::TaskId;
+use crate::task::Task;
+use crate::workspace::{Workspace, INBOX_FNAME};
+use speedate::Date;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+pub const WAITING_LABEL: &str = "@waiting";
+
+/// What a review found in a single project.
+#[derive(Debug, Clone)]
+pub struct ProjectReview {
+    /// Relative to the root.
+    pub path: PathBuf,
+    pub open: usize,
+    pub waiting: Vec<Task>,
+    pub overdue: Vec<Task>,
+    /// Open tasks that didn't change for too long, with the days since they did.
+    pub stale: Vec<(Task, i64)>,
+}
+
+impl ProjectReview {
+    pub fn is_inbox(&self) -> bool {
+        self.path == Path::new(INBOX_FNAME)
+    }
+
+    pub fn needs_attention(&self) -> bool {
+        self.open == 0
+            || !self.waiting.is_empty()
+            || !self.overdue.is_empty()
+            || !self.stale.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Review {
+    /// The day of the previous review.
+    pub last: Option<Date>,
+    /// The inbox first, then the other projects by path.
+    pub projects: Vec<ProjectReview>,
+    today: Date,
+    history: History,
+}
+
+impl Review {
+    /// Goes through every project. Tasks in the inbox are stale after
+    /// `inbox_days`, the others after `stale_days`. Tasks that start later are
+    /// not stale, they are not meant to be done yet.
+    pub fn new(ws: &Workspace, stale_days: i64, inbox_days: i64, today: &Date) -> Result<Review> {
+        let mut history = ws.history()?;
+        let mut files: Vec<PathBuf> = ws
+            .files()?
+            .iter()
+            .map(|f| ws.relative(f).to_path_buf())
+            .collect();
+        files.sort_by_key(|f| (f != Path::new(INBOX_FNAME), f.clone()));
+        let mut ids: HashSet<TaskId> = HashSet::new();
+        let mut projects = Vec::new();
+        for path in files {
+            let tasks = ws.file_tasks(&ws.root().join(&path))?;
+            history.notice(&tasks, today);
+            ids.extend(tasks.iter().filter_map(|t| t.id().cloned()));
+            let max_days = if path == Path::new(INBOX_FNAME) {
+                inbox_days
+            } else {
+                stale_days
+            };
+            let mut project = ProjectReview {
+                path,
+                open: 0,
+                waiting: Vec::new(),
+                overdue: Vec::new(),
+                stale: Vec::new(),
+            };
+            for task in tasks.into_iter().filter(|t| !t.status().is_closed()) {
+                project.open += 1;
+                if task.has_label(WAITING_LABEL) {
+                    project.waiting.push(task.clone());
+                }
+                if task.date().is_some_and(|d| d < today) {
+                    project.overdue.push(task.clone());
+                }
+                let days = task
+                    .id()
+                    .and_then(|id| history.changed(id))
+                    .map(|changed| (to_naive(today) - to_naive(changed)).num_days())
+                    .unwrap_or(0);
+                if days >= max_days && !task.is_deferred(today) {
+                    project.stale.push((task, days));
+                }
+            }
+            projects.push(project);
+        }
+        history.retain(&ids);
+        Ok(Review {
+            last: history.reviewed.clone(),
+            projects,
+            today: today.clone(),
+            history,
+        })
+    }
+
+    /// Remembers that the review is done, today.
+    pub fn finish(mut self, ws: &Workspace) -> Result<()> {
+        self.history.reviewed = Some(self.today);
+        self.history.save(ws.root())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::parse_task;
+    use crate::testing::TempRoot;
+
+    fn date(s: &str) -> Date {
+        Date::parse_str(s).unwrap()
+    }
+
+    fn ids<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> Vec<String> {
+        tasks
+            .into_iter()
+            .map(|t| t.id().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn finds_what_needs_attention() {
+        let root = TempRoot::new(&[
+            ("inbox.md", "- [ ] &1 Old inbox\n- [ ] &2 Fresh inbox\n"),
+            (
+                "work.md",
+                "- [ ] &3 Old work\n- [ ] &4 Recent work\n- [ ] &5 Later ^2024-04-01\n\
+                 - [ ] &6 New\n- [ ] &7 Edited by hand\n- [ ] &8 Wait @waiting %2024-02-01\n\
+                 - [x] &9 Done\n",
+            ),
+            ("done.md", "- [x] &10 Done\n"),
+        ]);
+        let mut history = History::default();
+        for (line, day) in [
+            ("- [ ] &1 Old inbox", "2024-02-25"),
+            ("- [ ] &2 Fresh inbox", "2024-02-29"),
+            ("- [ ] &3 Old work", "2024-02-01"),
+            ("- [ ] &4 Recent work", "2024-02-25"),
+            ("- [ ] &5 Later ^2024-04-01", "2024-01-01"),
+            ("- [ ] &7 Edited", "2024-01-01"),
+            ("- [ ] &8 Wait @waiting %2024-02-01", "2024-02-25"),
+            ("- [x] &9 Done", "2024-01-01"),
+            ("- [ ] &11 Gone", "2024-01-01"),
+        ] {
+            history.touch(&parse_task(line).unwrap(), &date(day));
+        }
+        history.save(root.path()).unwrap();
+        let ws = Workspace::open(root.path()).unwrap();
+        let today = date("2024-03-01");
+        let review = Review::new(&ws, 14, 3, &today).unwrap();
+        assert_eq!(review.last, None);
+        let paths: Vec<&Path> = review.projects.iter().map(|p| p.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("inbox.md"),
+                Path::new("done.md"),
+                Path::new("work.md")
+            ]
+        );
+        let [inbox, done, work] = &review.projects[..] else {
+            unreachable!()
+        };
+        // Inbox tasks get stale sooner, deferred and new tasks not at all, and
+        // a task edited by hand counts as changed today.
+        assert_eq!(ids(inbox.stale.iter().map(|(t, _)| t)), ["1"]);
+        assert_eq!(inbox.stale[0].1, 5);
+        assert_eq!(ids(work.stale.iter().map(|(t, _)| t)), ["3"]);
+        assert_eq!(work.stale[0].1, 29);
+        assert_eq!(ids(&work.waiting), ["8"]);
+        assert_eq!(ids(&work.overdue), ["8"]);
+        assert_eq!(work.open, 6);
+        assert!(inbox.needs_attention());
+        assert_eq!(done.open, 0);
+        assert!(done.needs_attention());
+
+        // Nothing is written until the review is done.
+        assert_eq!(History::load(root.path()).unwrap(), history);
+        review.finish(&ws).unwrap();
+        let history = ws.history().unwrap();
+        assert_eq!(history.reviewed, Some(today.clone()));
+        assert_eq!(history.changed(&TaskId::Number(7)), Some(&today));
+        assert_eq!(history.changed(&TaskId::Number(6)), Some(&today));
+        assert_eq!(history.changed(&TaskId::Number(11)), None);
+        let review = Review::new(&ws, 14, 3, &today).unwrap();
+        assert_eq!(review.last, Some(today));
+    }
+}
@@ -0,0 +1,400 @@
+//! Crash-safe writes.
+//!
+//! A file is never truncated in place. The new content goes to a temporary file
+//! next to it, gets fsynced and then renamed over the original, so a file is
+//! always either the old or the new version.
+//!
+//! Writes that span several files (e.g. moving a task) go through a
+//! [`Transaction`]. Before any rename happens, the list of renames is saved to a
+//! journal in the root. If rtd dies halfway, [`recover`] finishes the renames on
+//! the next run, so a task is never lost or duplicated.
+//!
+//! Several rtd processes can run at once, e.g. `rtd tui` and a command in
+//! another terminal. Every process stages its own temporary files, and a lock
+//! file in the root makes sure only one of them commits or recovers at a time,
+//! so nobody replays a journal that is still being written.
+
+use crate::error::{Error, Result};
+use std::fs::{self, File, TryLockError};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const JOURNAL_FNAME: &str = ".rtd-journal";
+pub const LOCK_FNAME: &str = ".rtd-lock";
+pub const TMP_SUFFIX: &str = ".rtd-tmp";
+
+/// Makes the temporary files of a process unique.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Replaces the content of `path` atomically.
+pub fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let tmp = write_tmp(path, content)?;
+    rename(&tmp, path)
+}
+
+/// Writes to several files so that either all of them change or none does.
+#[derive(Debug)]
+pub struct Transaction {
+    root: PathBuf,
+    writes: Vec<(PathBuf, String)>,
+}
+
+impl Transaction {
+    /// The journal is kept in `root`.
+    pub fn new(root: &Path) -> Transaction {
+        Transaction {
+            root: root.to_path_buf(),
+            writes: Vec::new(),
+        }
+    }
+
+    /// Schedules a write. Writing the same file twice keeps the last content.
+    pub fn write(&mut self, path: &Path, content: String) {
+        self.writes.retain(|(p, _)| p != path);
+        self.writes.push((path.to_path_buf(), content));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn commit(self) -> Result<()> {
+        match self.writes.as_slice() {
+            [] => return Ok(()),
+            [(path, content)] => return write_atomic(path, content),
+            _ => {}
+        }
+
+        // Released when dropped, at the end of the commit.
+        let _lock = lock(&self.root)?;
+        // A crashed transaction has to finish before its journal is replaced.
+        recover_locked(&self.root)?;
+
+        let mut staged: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for (path, content) in self.writes.iter() {
+            match write_tmp(path, content) {
+                Ok(tmp) => staged.push((tmp, path.clone())),
+                Err(e) => {
+                    // Nothing has been renamed yet, so the files are untouched.
+                    for (tmp, _) in staged {
+                        let _ = fs::remove_file(tmp);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let journal: String = staged
+            .iter()
+            .map(|(tmp, path)| format!("{}\t{}\n", tmp.display(), path.display()))
+            .collect();
+        let journal_path = self.root.join(JOURNAL_FNAME);
+        if let Err(e) = write_atomic(&journal_path, &journal) {
+            for (tmp, _) in staged {
+                let _ = fs::remove_file(tmp);
+            }
+            return Err(e);
+        }
+
+        // From here on, the journal makes sure the renames finish.
+        for (tmp, path) in staged.iter() {
+            rename(tmp, path)?;
+        }
+        fs::remove_file(&journal_path).map_err(|e| Error::io(&journal_path, e))
+    }
+}
+
+/// Finishes a transaction interrupted by a crash, if there is one in `root`.
+/// Returns the files that were written. If another process holds the lock,
+/// its transaction is not finished yet and nothing is done.
+pub fn recover(root: &Path) -> Result<Vec<PathBuf>> {
+    let journal_path = root.join(JOURNAL_FNAME);
+    if !journal_path.exists() {
+        return Ok(Vec::new());
+    }
+    let Some(_lock) = try_lock(root)? else {
+        return Ok(Vec::new());
+    };
+    recover_locked(root)
+}
+
+fn recover_locked(root: &Path) -> Result<Vec<PathBuf>> {
+    let journal_path = root.join(JOURNAL_FNAME);
+    if !journal_path.exists() {
+        return Ok(Vec::new());
+    }
+    let journal = fs::read_to_string(&journal_path).map_err(|e| Error::io(&journal_path, e))?;
+    let mut recovered = Vec::new();
+    for line in journal.lines() {
+        if let Some((tmp, path)) = line.split_once('\t') {
+            let (tmp, path) = (Path::new(tmp), Path::new(path));
+            // A missing temporary file has already been renamed.
+            if tmp.exists() {
+                rename(tmp, path)?;
+                recovered.push(path.to_path_buf());
+            }
+        }
+    }
+    fs::remove_file(&journal_path).map_err(|e| Error::io(&journal_path, e))?;
+    Ok(recovered)
+}
+
+fn open_lock(root: &Path) -> Result<File> {
+    let path = root.join(LOCK_FNAME);
+    File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| Error::io(&path, e))
+}
+
+/// Waits until no other process commits in `root`. The lock is released when
+/// the file is dropped, or when the process dies.
+fn lock(root: &Path) -> Result<File> {
+    let file = open_lock(root)?;
+    file.lock()
+        .map_err(|e| Error::io(root.join(LOCK_FNAME), e))?;
+    Ok(file)
+}
+
+/// [`lock`] without waiting, None if another process holds it.
+fn try_lock(root: &Path) -> Result<Option<File>> {
+    let file = open_lock(root)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(Error::io(root.join(LOCK_FNAME), e)),
+    }
+}
+
+/// The temporary file lives in the same directory, a rename across file systems
+/// would not be atomic. Its name is new every time, so that two processes
+/// writing the same file don't overwrite each other's staged content.
+fn tmp_path(path: &Path) -> PathBuf {
+    let fname = path
+        .file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or_default();
+    let n = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(
+        ".{}.{}-{}{}",
+        fname,
+        std::process::id(),
+        n,
+        TMP_SUFFIX
+    ))
+}
+
+fn write_tmp(path: &Path, content: &str) -> Result<PathBuf> {
+    let (tmp, mut f) = loop {
+        let tmp = tmp_path(path);
+        match File::options().write(true).create_new(true).open(&tmp) {
+            Ok(f) => break (tmp, f),
+            // Left by a crashed process with the same pid, take the next name.
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::io(&tmp, e)),
+        }
+    };
+    let mut write = || -> std::io::Result<()> {
+        // The renamed file should keep the permissions of the one it replaces.
+        if let Ok(meta) = fs::metadata(path) {
+            f.set_permissions(meta.permissions())?;
+        }
+        f.write_all(content.as_bytes())?;
+        f.sync_all()
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&tmp);
+        return Err(Error::io(&tmp, e));
+    }
+    Ok(tmp)
+}
+
+fn rename(tmp: &Path, path: &Path) -> Result<()> {
+    fs::rename(tmp, path).map_err(|e| Error::io(path, e))?;
+    sync_dir(path)
+}
+
+/// Makes the rename itself durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| Error::io(dir, e))
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempRoot;
+
+    /// The files in the root but the given ones and the lock, e.g. stray
+    /// temporary files.
+    fn others(root: &TempRoot, known: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(root.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|n| n != LOCK_FNAME && !known.contains(&n.as_str()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// A journal as a crashed commit leaves it.
+    fn journal(staged: &[(PathBuf, PathBuf)]) -> String {
+        staged
+            .iter()
+            .map(|(tmp, path)| format!("{}\t{}\n", tmp.display(), path.display()))
+            .collect()
+    }
+
+    /// Stages new content for the file, as the first half of a commit does.
+    fn stage(root: &TempRoot, path: &str, content: &str) -> (PathBuf, PathBuf) {
+        let path = root.join(path);
+        (write_tmp(&path, content).unwrap(), path)
+    }
+
+    /// A staged file that was renamed already.
+    fn renamed(root: &TempRoot, path: &str) -> (PathBuf, PathBuf) {
+        let path = root.join(path);
+        (tmp_path(&path), path)
+    }
+
+    #[test]
+    fn commit_writes_every_file() {
+        let root = TempRoot::new(&[("a.md", "old a\n"), ("b.md", "old b\n")]);
+        let mut transaction = Transaction::new(root.path());
+        transaction.write(&root.join("a.md"), "first\n".to_string());
+        transaction.write(&root.join("b.md"), "new b\n".to_string());
+        transaction.write(&root.join("a.md"), "new a\n".to_string());
+        transaction.commit().unwrap();
+        assert_eq!(root.read("a.md"), "new a\n");
+        assert_eq!(root.read("b.md"), "new b\n");
+        assert!(others(&root, &["a.md", "b.md"]).is_empty());
+    }
+
+    #[test]
+    fn temporary_files_are_never_shared() {
+        let root = TempRoot::new(&[("a.md", "old\n")]);
+        let (first, _) = stage(&root, "a.md", "first\n");
+        let (second, _) = stage(&root, "a.md", "second\n");
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "first\n");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "second\n");
+        assert!(first.to_string_lossy().ends_with(TMP_SUFFIX));
+    }
+
+    #[test]
+    fn recover_finishes_a_staged_transaction() {
+        let root = TempRoot::new(&[("a.md", "old a\n"), ("sub/b.md", "old b\n")]);
+        // The temporary files and the journal are written, nothing renamed.
+        let staged = [
+            stage(&root, "a.md", "new a\n"),
+            stage(&root, "sub/b.md", "new b\n"),
+        ];
+        root.write(JOURNAL_FNAME, &journal(&staged));
+
+        let recovered = recover(root.path()).unwrap();
+        assert_eq!(recovered, [root.join("a.md"), root.join("sub/b.md")]);
+        assert_eq!(root.read("a.md"), "new a\n");
+        assert_eq!(root.read("sub/b.md"), "new b\n");
+        assert!(others(&root, &["a.md", "sub"]).is_empty());
+        assert!(!staged[1].0.exists());
+    }
+
+    #[test]
+    fn recover_skips_the_renamed_files() {
+        let root = TempRoot::new(&[("a.md", "new a\n"), ("b.md", "old b\n")]);
+        // a.md was renamed before the crash, b.md wasn't.
+        let staged = [renamed(&root, "a.md"), stage(&root, "b.md", "new b\n")];
+        root.write(JOURNAL_FNAME, &journal(&staged));
+
+        assert_eq!(recover(root.path()).unwrap(), [root.join("b.md")]);
+        assert_eq!(root.read("a.md"), "new a\n");
+        assert_eq!(root.read("b.md"), "new b\n");
+        assert!(others(&root, &["a.md", "b.md"]).is_empty());
+    }
+
+    #[test]
+    fn recover_after_every_rename_changes_nothing() {
+        let root = TempRoot::new(&[("a.md", "new a\n"), ("b.md", "new b\n")]);
+        let staged = [renamed(&root, "a.md"), renamed(&root, "b.md")];
+        root.write(JOURNAL_FNAME, &journal(&staged));
+
+        assert!(recover(root.path()).unwrap().is_empty());
+        assert_eq!(root.read("a.md"), "new a\n");
+        assert_eq!(root.read("b.md"), "new b\n");
+        assert!(others(&root, &["a.md", "b.md"]).is_empty());
+        // Without a journal there is nothing to do.
+        assert!(recover(root.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn recover_leaves_a_commit_in_progress_alone() {
+        let root = TempRoot::new(&[("a.md", "old a\n"), ("b.md", "old b\n")]);
+        let staged = [
+            stage(&root, "a.md", "new a\n"),
+            stage(&root, "b.md", "new b\n"),
+        ];
+        root.write(JOURNAL_FNAME, &journal(&staged));
+        // Another process is committing.
+        let held = lock(root.path()).unwrap();
+        assert!(recover(root.path()).unwrap().is_empty());
+        assert_eq!(root.read("a.md"), "old a\n");
+        assert!(root.join(JOURNAL_FNAME).exists());
+        assert!(staged.iter().all(|(tmp, _)| tmp.exists()));
+
+        // It died, which releases the lock.
+        drop(held);
+        assert_eq!(recover(root.path()).unwrap().len(), 2);
+        assert_eq!(root.read("a.md"), "new a\n");
+    }
+
+    #[test]
+    fn commit_finishes_a_crashed_transaction_first() {
+        let root = TempRoot::new(&[
+            ("a.md", "old a\n"),
+            ("b.md", "old b\n"),
+            ("c.md", "old c\n"),
+        ]);
+        let staged = [
+            stage(&root, "a.md", "crashed a\n"),
+            stage(&root, "b.md", "crashed b\n"),
+        ];
+        root.write(JOURNAL_FNAME, &journal(&staged));
+
+        let mut transaction = Transaction::new(root.path());
+        transaction.write(&root.join("b.md"), "new b\n".to_string());
+        transaction.write(&root.join("c.md"), "new c\n".to_string());
+        transaction.commit().unwrap();
+        assert_eq!(root.read("a.md"), "crashed a\n");
+        assert_eq!(root.read("b.md"), "new b\n");
+        assert_eq!(root.read("c.md"), "new c\n");
+        assert!(others(&root, &["a.md", "b.md", "c.md"]).is_empty());
+    }
+
+    #[test]
+    fn failed_write_changes_nothing() {
+        let root = TempRoot::new(&[("a.md", "old a\n"), ("b.md", "old b\n")]);
+        let mut transaction = Transaction::new(root.path());
+        transaction.write(&root.join("a.md"), "new a\n".to_string());
+        transaction.write(&root.join("b.md"), "new b\n".to_string());
+        // The folder doesn't exist, so its temporary file can't be made.
+        transaction.write(&root.join("missing/c.md"), "new c\n".to_string());
+        assert!(transaction.commit().is_err());
+        assert_eq!(root.read("a.md"), "old a\n");
+        assert_eq!(root.read("b.md"), "old b\n");
+        assert!(others(&root, &["a.md", "b.md"]).is_empty());
+    }
+}
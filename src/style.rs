@@ -0,0 +1,187 @@
+//! Colours for tasks printed to a terminal.
+//!
+//! Only the terminal output is coloured: overdue dates are red, today's are
+//! yellow, labels are dimmed and closed tasks are struck through. The text
+//! itself is the same as the markdown line, apart from the ids, which are
+//! padded so that they are right-aligned in a listing.
+
+use crate::task::{Task, TokenKind};
+use speedate::Date;
+use std::fmt;
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+const RED: (&str, &str) = ("\x1b[31m", "\x1b[39m");
+const YELLOW: (&str, &str) = ("\x1b[33m", "\x1b[39m");
+const DIM: (&str, &str) = ("\x1b[2m", "\x1b[22m");
+const BOLD: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+const STRIKE: (&str, &str) = ("\x1b[9m", "\x1b[29m");
+
+/// When to colour the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When stdout is a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+fn paint(text: &str, (on, off): (&str, &str)) -> String {
+    format!("{}{}{}", on, text, off)
+}
+
+pub fn heading(text: &str) -> String {
+    paint(text, BOLD)
+}
+
+/// How many characters the widest id of `tasks` takes.
+pub fn id_width<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> usize {
+    tasks
+        .into_iter()
+        .filter_map(|t| t.id())
+        .map(|id| id.to_string().len())
+        .max()
+        .unwrap_or(0)
+}
+
+/// The task line with colours, its id padded to `id_width` characters.
+/// Everything between the tokens, tabs too, is kept as it is.
+pub fn task_line(task: &Task, today: &Date, id_width: usize) -> String {
+    let line = task.line();
+    let indent = line.len() - line.trim_start().len();
+    // The checkbox, `- [ ]`, is 5 bytes long.
+    let head = &line[..indent + 5];
+    let date_color = match task.date() {
+        _ if task.status().is_closed() => None,
+        Some(d) if d < today => Some(RED),
+        Some(d) if d == today => Some(YELLOW),
+        _ => None,
+    };
+    let mut body = String::new();
+    let mut end = head.len();
+    let mut padded = false;
+    for (kind, span) in task.tokens() {
+        body.push_str(&line[end..span.start]);
+        let token = &line[span.clone()];
+        match kind {
+            TokenKind::Label => body.push_str(&paint(token, DIM)),
+            TokenKind::Date if date_color.is_some() => {
+                body.push_str(&paint(token, date_color.expect("Checked above.")))
+            }
+            TokenKind::Id => {
+                padded = true;
+                body.push_str(&format!("{:>width$}", token, width = id_width + 1));
+            }
+            _ => body.push_str(token),
+        }
+        end = span.end;
+    }
+    body.push_str(&line[end..]);
+    if !padded && id_width > 0 {
+        body.insert_str(0, &" ".repeat(id_width + 2));
+    }
+    if task.status().is_closed() {
+        // Keep the space after the checkbox as it is.
+        let text = body.trim_start();
+        let space = &body[..body.len() - text.len()];
+        body = format!("{}{}", space, paint(text, STRIKE));
+    }
+    format!("{}{}", head, body)
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorChoice::Auto => "auto",
+            ColorChoice::Always => "always",
+            ColorChoice::Never => "never",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "Unknown color choice '{}', use auto, always or never.",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::parse_task;
+
+    fn line(task: &str, id_width: usize) -> String {
+        let today = Date::parse_str("2024-01-25").unwrap();
+        task_line(&parse_task(task).unwrap(), &today, id_width)
+    }
+
+    #[test]
+    fn colours_dates_by_the_day() {
+        assert_eq!(
+            line("- [ ] A %2024-01-24", 0),
+            "- [ ] A \x1b[31m%2024-01-24\x1b[39m"
+        );
+        assert_eq!(
+            line("- [ ] A %2024-01-25T8:00 B", 0),
+            "- [ ] A \x1b[33m%2024-01-25T8:00\x1b[39m B"
+        );
+        assert_eq!(line("- [ ] A %2024-01-26", 0), "- [ ] A %2024-01-26");
+        // Only the date of the task, the earlier one is a part of the title.
+        assert_eq!(
+            line("- [ ] %2024-01-20 %2024-01-20", 0),
+            "- [ ] %2024-01-20 \x1b[31m%2024-01-20\x1b[39m"
+        );
+    }
+
+    #[test]
+    fn dims_labels() {
+        assert_eq!(
+            line("- [ ] Mail a@b.c @home\t@next", 0),
+            "- [ ] Mail a@b.c \x1b[2m@home\x1b[22m\t\x1b[2m@next\x1b[22m"
+        );
+    }
+
+    #[test]
+    fn strikes_closed_tasks_through() {
+        assert_eq!(
+            line("- [x] &7 A %2024-01-24", 2),
+            "- [x]  \x1b[9m&7 A %2024-01-24\x1b[29m"
+        );
+    }
+
+    #[test]
+    fn pads_ids() {
+        assert_eq!(line("- [ ] &7 A", 3), "- [ ]   &7 A");
+        assert_eq!(line("- [ ]\t&7\tA", 3), "- [ ]\t  &7\tA");
+        assert_eq!(line("  - [ ] &123 A", 3), "  - [ ] &123 A");
+        // Tasks without an id line up with the ones with it.
+        assert_eq!(line("- [ ] A", 3), "- [ ]      A");
+        assert_eq!(line("- [ ] A &7", 1), "- [ ]    A &7");
+        assert_eq!(line("- [ ] &7 A", 0), "- [ ] &7 A");
+    }
+}
@@ -0,0 +1,731 @@
+use crate::dates::{due_at, format_due, parse_due};
+use crate::id::TaskId;
+use crate::recurrence::Recurrence;
+use chrono::{NaiveDateTime, NaiveTime};
+use regex::Regex;
+use speedate::Date;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+pub const TASK_UNDONE: &str = "- [ ]";
+pub const TASK_DONE: &str = "- [x]";
+
+/// The state of the checkbox, as used by Obsidian and other markdown task plugins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TaskStatus {
+    /// `- [ ]`
+    Todo,
+    /// `- [x]` or `- [X]`
+    Done,
+    /// `- [-]`
+    Cancelled,
+    /// `- [/]`
+    InProgress,
+    /// `- [>]`, forwarded or deferred to later.
+    Forwarded,
+}
+
+impl TaskStatus {
+    pub const ALL: [TaskStatus; 5] = [
+        TaskStatus::Todo,
+        TaskStatus::Done,
+        TaskStatus::Cancelled,
+        TaskStatus::InProgress,
+        TaskStatus::Forwarded,
+    ];
+
+    fn from_checkbox(c: char) -> Option<TaskStatus> {
+        match c {
+            ' ' => Some(TaskStatus::Todo),
+            'x' | 'X' => Some(TaskStatus::Done),
+            '-' => Some(TaskStatus::Cancelled),
+            '/' => Some(TaskStatus::InProgress),
+            '>' => Some(TaskStatus::Forwarded),
+            _ => None,
+        }
+    }
+
+    fn checkbox(&self) -> char {
+        match self {
+            TaskStatus::Todo => ' ',
+            TaskStatus::Done => 'x',
+            TaskStatus::Cancelled => '-',
+            TaskStatus::InProgress => '/',
+            TaskStatus::Forwarded => '>',
+        }
+    }
+
+    /// Done and cancelled tasks need no more work.
+    pub fn is_closed(&self) -> bool {
+        matches!(self, TaskStatus::Done | TaskStatus::Cancelled)
+    }
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TaskStatus::Todo => "todo",
+            TaskStatus::Done => "done",
+            TaskStatus::Cancelled => "cancelled",
+            TaskStatus::InProgress => "in-progress",
+            TaskStatus::Forwarded => "forwarded",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for TaskStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TaskStatus, String> {
+        match s {
+            "todo" | "open" => Ok(TaskStatus::Todo),
+            "done" => Ok(TaskStatus::Done),
+            "cancelled" | "canceled" => Ok(TaskStatus::Cancelled),
+            "in-progress" | "started" => Ok(TaskStatus::InProgress),
+            "forwarded" | "deferred" => Ok(TaskStatus::Forwarded),
+            _ => Err(format!(
+                "Unknown status '{}', use one of: todo, done, cancelled, in-progress, forwarded.",
+                s
+            )),
+        }
+    }
+}
+
+/// Task priority, from 1 (the most important) to 4.
+/// Written as `!1`..`!4` or, todo.txt style, as `(A)`..`(D)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(u8);
+
+impl Priority {
+    pub const HIGHEST: Priority = Priority(1);
+    pub const LOWEST: Priority = Priority(4);
+
+    pub fn new(level: u8) -> Option<Priority> {
+        (1..=4).contains(&level).then_some(Priority(level))
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    /// Parses a priority token, `!1` or `(A)`.
+    fn from_token(token: &str) -> Option<Priority> {
+        match token.as_bytes() {
+            [b'!', d @ b'1'..=b'4'] => Some(Priority(d - b'0')),
+            [b'(', l @ b'A'..=b'D', b')'] => Some(Priority(l - b'A' + 1)),
+            _ => None,
+        }
+    }
+
+    /// Writes the priority in the same style as `token`.
+    fn to_token(self, token: &str) -> String {
+        if token.starts_with('(') {
+            format!("({})", (b'A' + self.0 - 1) as char)
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "!{}", self.0)
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    /// Accepts `1`, `!1`, `A`, `a` and `(A)`.
+    fn from_str(s: &str) -> Result<Priority, String> {
+        let token = match s.as_bytes() {
+            [d @ b'1'..=b'4'] => format!("!{}", *d as char),
+            [l @ (b'a'..=b'd' | b'A'..=b'D')] => format!("({})", l.to_ascii_uppercase() as char),
+            _ => s.to_string(),
+        };
+        Priority::from_token(&token)
+            .ok_or_else(|| format!("Unknown priority '{}', use 1 to 4 or A to D.", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TokenKind {
+    Id,
+    Priority,
+    Date,
+    Start,
+    Recurrence,
+    Label,
+    Word,
+}
+
+/// A whitespace separated piece of the task line and where it is.
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    span: Range<usize>,
+}
+
+/// A task parsed from a single markdown line.
+///
+/// The task keeps the line it was parsed from. Rendering an untouched task gives
+/// back exactly that line, and the setters only rewrite the tokens they change.
+///
+/// Indented tasks are subtasks of the closest task above them with a smaller
+/// indentation. Indented lines that are not tasks are the notes of that task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    line: String,
+    tokens: Vec<Token>,
+    /// Length of the whitespace before the checkbox, in bytes.
+    indent_len: usize,
+    parent: Option<TaskId>,
+    notes: Vec<String>,
+    line_number: Option<usize>,
+    status: TaskStatus,
+    id: Option<TaskId>,
+    title: String,
+    date: Option<Date>,
+    time: Option<NaiveTime>,
+    start: Option<Date>,
+    labels: Vec<String>,
+    priority: Option<Priority>,
+    recurrence: Option<Recurrence>,
+}
+
+impl Task {
+    pub fn status(&self) -> TaskStatus {
+        self.status
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.status == TaskStatus::Done
+    }
+
+    /// None until the task gets an id assigned by the workspace.
+    pub fn id(&self) -> Option<&TaskId> {
+        self.id.as_ref()
+    }
+
+    /// The task description without the id, dates, priority, recurrence and labels.
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn date(&self) -> Option<&Date> {
+        self.date.as_ref()
+    }
+
+    /// The time of day the task is due, only tasks with a date can have it.
+    pub fn time(&self) -> Option<NaiveTime> {
+        self.time
+    }
+
+    /// The date and the time of the task. Without a time, the start of the day.
+    pub fn due_at(&self) -> Option<NaiveDateTime> {
+        self.date.as_ref().map(|d| due_at(d, self.time))
+    }
+
+    /// The day the task becomes actionable, written as `^2024-03-01`.
+    pub fn start(&self) -> Option<&Date> {
+        self.start.as_ref()
+    }
+
+    /// Returns true if the task has a start date after today.
+    pub fn is_deferred(&self, today: &Date) -> bool {
+        matches!(&self.start, Some(start) if start > today)
+    }
+
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
+    pub fn recurrence(&self) -> Option<&Recurrence> {
+        self.recurrence.as_ref()
+    }
+
+    /// We store labels together with the @ sign.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The markdown line of the task, without the line ending.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Width of the indentation, a tab counts as 4 spaces.
+    pub fn indent(&self) -> usize {
+        indent_width(&self.line)
+    }
+
+    /// The id of the task this one is a subtask of.
+    pub fn parent(&self) -> Option<&TaskId> {
+        self.parent.as_ref()
+    }
+
+    pub(crate) fn set_parent(&mut self, parent: Option<TaskId>) {
+        self.parent = parent;
+    }
+
+    /// The line of the task in its file, starting from 1.
+    /// None for tasks that were not read from a file.
+    pub fn line_number(&self) -> Option<usize> {
+        self.line_number
+    }
+
+    pub(crate) fn set_line_number(&mut self, line_number: usize) {
+        self.line_number = Some(line_number);
+    }
+
+    /// Lines under the task that are not tasks, e.g. descriptions, links or
+    /// code. The indentation they have in common is stripped.
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub(crate) fn set_notes(&mut self, notes: Vec<String>) {
+        let indent = notes
+            .iter()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.len() - l.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        self.notes = notes
+            .into_iter()
+            .map(|l| l.get(indent..).unwrap_or("").to_string())
+            .collect();
+    }
+
+    /// Returns true if the task has a date set and it's today or earlier.
+    pub fn is_due(&self, today: &Date) -> bool {
+        matches!(&self.date, Some(date) if date <= today)
+    }
+
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|l| l == label)
+    }
+
+    /// All http(s) links in the task description and its notes.
+    pub fn urls(&self) -> Vec<String> {
+        let re = Regex::new(r"http://\S+|https://\S+").unwrap();
+        std::iter::once(&self.title)
+            .chain(self.notes.iter())
+            .flat_map(|text| re.find_iter(text))
+            .map(|m| m.as_str().to_string())
+            .collect()
+    }
+
+    /// Only the character in the checkbox changes.
+    pub fn set_status(&mut self, status: TaskStatus) {
+        if self.status != status {
+            // The checkbox character comes after "- [".
+            let at = self.indent_len + 3;
+            self.edit(at..at + 1, &status.checkbox().to_string());
+        }
+    }
+
+    pub fn set_done(&mut self, is_done: bool) {
+        self.set_status(if is_done {
+            TaskStatus::Done
+        } else {
+            TaskStatus::Todo
+        });
+    }
+
+    pub fn set_id(&mut self, id: &TaskId) {
+        let id_token = format!("&{}", id);
+        match self.span_of(TokenKind::Id) {
+            Some(span) => self.edit(span, &id_token),
+            // The id always goes right after the checkbox.
+            None => match self.tokens.first() {
+                Some(first) => self.edit(first.span.start..first.span.start, &(id_token + " ")),
+                None => self.append(&id_token),
+            },
+        }
+    }
+
+    /// Keeps the time the task already has. None removes the time too.
+    pub fn set_date(&mut self, date: Option<Date>) {
+        let token = date.map(|d| format!("%{}", format_due(&d, self.time)));
+        match (self.span_of(TokenKind::Date), token) {
+            (Some(span), Some(token)) => self.edit(span, &token),
+            (Some(span), None) => self.remove_token(span),
+            (None, Some(token)) => self.append(&token),
+            (None, None) => {}
+        }
+    }
+
+    /// Does nothing if the task has no date.
+    pub fn set_time(&mut self, time: Option<NaiveTime>) {
+        if let (Some(span), Some(date)) = (self.span_of(TokenKind::Date), &self.date) {
+            let token = format!("%{}", format_due(date, time));
+            self.edit(span, &token);
+        }
+    }
+
+    pub fn set_start(&mut self, start: Option<Date>) {
+        match (self.span_of(TokenKind::Start), start) {
+            (Some(span), Some(start)) => self.edit(span, &format!("^{}", start)),
+            (Some(span), None) => self.remove_token(span),
+            (None, Some(start)) => self.append(&format!("^{}", start)),
+            (None, None) => {}
+        }
+    }
+
+    /// Keeps the style of the priority the task already has, `!1` otherwise.
+    pub fn set_priority(&mut self, priority: Option<Priority>) {
+        match (self.span_of(TokenKind::Priority), priority) {
+            (Some(span), Some(priority)) => {
+                let token = priority.to_token(&self.line[span.clone()]);
+                self.edit(span, &token)
+            }
+            (Some(span), None) => self.remove_token(span),
+            (None, Some(priority)) => self.append(&priority.to_string()),
+            (None, None) => {}
+        }
+    }
+
+    pub fn set_recurrence(&mut self, recurrence: Option<Recurrence>) {
+        match (self.span_of(TokenKind::Recurrence), recurrence) {
+            (Some(span), Some(recurrence)) => self.edit(span, &recurrence.to_string()),
+            (Some(span), None) => self.remove_token(span),
+            (None, Some(recurrence)) => self.append(&recurrence.to_string()),
+            (None, None) => {}
+        }
+    }
+
+    pub fn add_label(&mut self, label: &str) {
+        self.append(label);
+    }
+
+    /// Removes every occurrence of the label.
+    pub fn remove_label(&mut self, label: &str) {
+        while let Some(span) = self
+            .tokens
+            .iter()
+            .find(|t| t.kind == TokenKind::Label && self.line[t.span.clone()] == *label)
+            .map(|t| t.span.clone())
+        {
+            self.remove_token(span);
+        }
+    }
+
+    /// Renames every occurrence of the label in place. A task that has the
+    /// new label already just loses the old one.
+    pub fn rename_label(&mut self, old: &str, new: &str) {
+        if old == new {
+            return;
+        }
+        if self.has_label(new) {
+            return self.remove_label(old);
+        }
+        while let Some(span) = self
+            .tokens
+            .iter()
+            .find(|t| t.kind == TokenKind::Label && self.line[t.span.clone()] == *old)
+            .map(|t| t.span.clone())
+        {
+            self.edit(span, new);
+        }
+    }
+
+    /// Replaces the words of the title. The new title goes right after the id,
+    /// the other tokens stay where they are. Returns false and keeps the task
+    /// as it is if `title` has more than words in it, e.g. a date or a label.
+    pub fn set_title(&mut self, title: &str) -> bool {
+        let only_words = parse_task(&format!("{} {}", TASK_UNDONE, title))
+            .is_some_and(|t| t.tokens.iter().all(|t| t.kind == TokenKind::Word));
+        if !only_words {
+            return false;
+        }
+        while let Some(span) = self
+            .tokens
+            .iter()
+            .rev()
+            .find(|t| t.kind == TokenKind::Word)
+            .map(|t| t.span.clone())
+        {
+            self.remove_token(span);
+        }
+        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !title.is_empty() {
+            let at = match self.span_of(TokenKind::Id) {
+                Some(span) => span.end,
+                None => self.indent_len + TASK_DONE.len(),
+            };
+            self.edit(at..at, &format!(" {}", title));
+        }
+        true
+    }
+
+    /// Replaces the whole line but its indentation, e.g. with a line edited
+    /// by hand. Returns false and keeps the task as it is if `text` is not a
+    /// task.
+    pub fn set_text(&mut self, text: &str) -> bool {
+        let text = text.trim();
+        if parse_task(text).is_none() {
+            return false;
+        }
+        self.edit(self.indent_len..self.line.len(), text);
+        true
+    }
+
+    /// The tokens after the checkbox, in order, with their byte ranges in
+    /// [`Task::line`].
+    pub(crate) fn tokens(&self) -> impl Iterator<Item = (TokenKind, Range<usize>)> + '_ {
+        self.tokens.iter().map(|t| (t.kind, t.span.clone()))
+    }
+
+    fn span_of(&self, kind: TokenKind) -> Option<Range<usize>> {
+        self.tokens
+            .iter()
+            .find(|t| t.kind == kind)
+            .map(|t| t.span.clone())
+    }
+
+    /// Adds a token after the last one, leaving trailing whitespace alone.
+    fn append(&mut self, token: &str) {
+        let end = self.line.trim_end().len();
+        self.edit(end..end, &format!(" {}", token));
+    }
+
+    /// Removes a token together with the whitespace before it.
+    fn remove_token(&mut self, span: Range<usize>) {
+        let start = self.line[..span.start].trim_end().len();
+        let start = start.max(self.indent_len + TASK_DONE.len());
+        self.edit(start..span.end, "");
+    }
+
+    fn edit(&mut self, span: Range<usize>, replacement: &str) {
+        let mut line = self.line.clone();
+        line.replace_range(span, replacement);
+        let parent = self.parent.take();
+        let notes = std::mem::take(&mut self.notes);
+        let line_number = self.line_number;
+        *self = parse_task(&line).expect("An edited task line is still a task.");
+        self.parent = parent;
+        self.notes = notes;
+        self.line_number = line_number;
+    }
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.line)
+    }
+}
+
+/// Splits `line` into whitespace separated spans, starting at `start`.
+fn token_spans(line: &str, start: usize) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut token_start = None;
+    for (i, c) in line[start..].char_indices() {
+        let i = i + start;
+        match (c.is_whitespace(), token_start) {
+            (true, Some(s)) => {
+                spans.push(s..i);
+                token_start = None;
+            }
+            (false, None) => token_start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = token_start {
+        spans.push(s..line.len());
+    }
+    spans
+}
+
+/// Width of the whitespace a line starts with, a tab counts as 4 spaces.
+pub(crate) fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// Parses a markdown checklist line into a task.
+/// The checklist item can be indented. Returns None if the line is not a task.
+pub fn parse_task(line: &str) -> Option<Task> {
+    let body = line.trim_start();
+    let indent_len = line.len() - body.len();
+    let status = match body.as_bytes() {
+        [b'-', b' ', b'[', c, b']', ..] => TaskStatus::from_checkbox(*c as char)?,
+        _ => return None,
+    };
+
+    let mut task = Task {
+        line: line.to_string(),
+        tokens: Vec::new(),
+        indent_len,
+        parent: None,
+        notes: Vec::new(),
+        line_number: None,
+        status,
+        id: None,
+        title: String::new(),
+        date: None,
+        time: None,
+        start: None,
+        labels: Vec::new(),
+        priority: None,
+        recurrence: None,
+    };
+    let mut task_body_vec: Vec<&str> = Vec::new();
+    let mut date_token = None;
+    let tokens_start = indent_len + TASK_DONE.len();
+    for (i, span) in token_spans(line, tokens_start).into_iter().enumerate() {
+        let v = &line[span.clone()];
+        let kind = if let Some(id) = v
+            .strip_prefix('&')
+            .and_then(|v| v.parse::<TaskId>().ok())
+            .filter(|_| i == 0)
+        {
+            // Only the first token can be an id.
+            task.id = Some(id);
+            TokenKind::Id
+        } else if let Some((date, time)) = v.strip_prefix('%').and_then(parse_due) {
+            // The last date wins, the ones before it become a part of the title.
+            if let Some(previous) = date_token.replace(task.tokens.len()) {
+                let previous: &mut Token = &mut task.tokens[previous];
+                previous.kind = TokenKind::Word;
+            }
+            task.date = Some(date);
+            task.time = time;
+            TokenKind::Date
+        } else if let Some(start) = v
+            .strip_prefix('^')
+            .and_then(|d| Date::parse_str_rfc3339(d).ok())
+            .filter(|_| task.start.is_none())
+        {
+            // The first start date wins.
+            task.start = Some(start);
+            TokenKind::Start
+        } else if let Some(priority) = Priority::from_token(v).filter(|_| task.priority.is_none()) {
+            // The first priority wins, the others are a part of the title.
+            task.priority = Some(priority);
+            TokenKind::Priority
+        } else if let Some(recurrence) =
+            Recurrence::from_token(v).filter(|_| task.recurrence.is_none())
+        {
+            // Same as with priorities, the first one wins.
+            task.recurrence = Some(recurrence);
+            TokenKind::Recurrence
+        } else if v.starts_with('@') {
+            task.labels.push(v.to_string());
+            TokenKind::Label
+        } else {
+            TokenKind::Word
+        };
+        task.tokens.push(Token { kind, span });
+    }
+    for t in task.tokens.iter().filter(|t| t.kind == TokenKind::Word) {
+        task_body_vec.push(&line[t.span.clone()]);
+    }
+    task.title = task_body_vec.join(" ");
+    Some(task)
+}
+
+/// Sorts tasks by priority, then by date and time; tasks without them go last.
+/// Subtasks stay under their parents and are sorted among their siblings.
+pub fn sort_tasks(tasks: &mut Vec<Task>) {
+    fn compare(a: &Task, b: &Task) -> Ordering {
+        let priority = |t: &Task| t.priority.map(|p| p.level()).unwrap_or(u8::MAX);
+        priority(a)
+            .cmp(&priority(b))
+            .then_with(|| match (a.due_at(), b.due_at()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            })
+    }
+
+    fn flatten(
+        mut siblings: Vec<Task>,
+        children: &mut HashMap<TaskId, Vec<Task>>,
+        out: &mut Vec<Task>,
+    ) {
+        siblings.sort_by(compare);
+        for task in siblings {
+            let subtasks = task.id().and_then(|id| children.remove(id));
+            out.push(task);
+            if let Some(subtasks) = subtasks {
+                flatten(subtasks, children, out);
+            }
+        }
+    }
+
+    let ids: HashSet<TaskId> = tasks.iter().filter_map(|t| t.id().cloned()).collect();
+    let mut roots = Vec::new();
+    let mut children: HashMap<TaskId, Vec<Task>> = HashMap::new();
+    for task in tasks.drain(..) {
+        // A subtask whose parent is not in the list is sorted with the top level tasks.
+        match task.parent().filter(|p| ids.contains(*p)).cloned() {
+            Some(parent) => children.entry(parent).or_default().push(task),
+            None => roots.push(task),
+        }
+    }
+    flatten(roots, &mut children, tasks);
+}
+
+/// Labels start with @ and have no spaces in them.
+pub fn is_valid_label(label: &str) -> bool {
+    label.len() > 1 && label.starts_with('@') && !label.contains(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renamed(line: &str, old: &str, new: &str) -> String {
+        let mut task = parse_task(line).unwrap();
+        task.rename_label(old, new);
+        task.line().to_string()
+    }
+
+    fn removed(line: &str, label: &str) -> String {
+        let mut task = parse_task(line).unwrap();
+        task.remove_label(label);
+        task.line().to_string()
+    }
+
+    #[test]
+    fn renames_labels_in_place() {
+        assert_eq!(
+            renamed("- [ ] &1 A @a B @a", "@a", "@b"),
+            "- [ ] &1 A @b B @b"
+        );
+        assert_eq!(
+            renamed("- [ ] &1 A\t@a  %2024-01-25", "@a", "@b"),
+            "- [ ] &1 A\t@b  %2024-01-25"
+        );
+        // Not a part of a longer label.
+        assert_eq!(renamed("- [ ] A @ab", "@a", "@b"), "- [ ] A @ab");
+        assert_eq!(renamed("- [ ] A @a", "@a", "@a"), "- [ ] A @a");
+    }
+
+    #[test]
+    fn renaming_onto_a_label_the_task_has_keeps_one() {
+        let line = renamed("- [ ] &1 A @a @b", "@a", "@b");
+        assert_eq!(line, "- [ ] &1 A @b");
+        assert_eq!(parse_task(&line).unwrap().labels(), ["@b"]);
+        assert_eq!(renamed("- [ ] &1 @b A @a", "@a", "@b"), "- [ ] &1 @b A");
+    }
+
+    #[test]
+    fn removes_every_occurrence_of_a_label() {
+        assert_eq!(removed("- [ ] &1 A @a B @a", "@a"), "- [ ] &1 A B");
+        assert_eq!(removed("- [ ] @a A", "@a"), "- [ ] A");
+        assert_eq!(removed("- [ ] A @b", "@a"), "- [ ] A @b");
+        let mut task = parse_task("  - [x] &2 A @a").unwrap();
+        task.remove_label("@a");
+        assert_eq!(task.line(), "  - [x] &2 A");
+        assert!(task.labels().is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+//! Helpers for the tests that need files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory of its own in the temporary directory, removed when dropped.
+pub struct TempRoot(PathBuf);
+
+impl TempRoot {
+    /// A new directory with `files` in it, given as a path relative to the
+    /// directory and the content.
+    pub fn new(files: &[(&str, &str)]) -> TempRoot {
+        let name = format!(
+            "rtd-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let root = TempRoot(std::env::temp_dir().join(name));
+        let _ = fs::remove_dir_all(root.path());
+        fs::create_dir_all(root.path()).unwrap();
+        for (path, content) in files {
+            root.write(path, content);
+        }
+        root
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.join(path)).unwrap()
+    }
+
+    pub fn write(&self, path: &str, content: &str) {
+        let path = self.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,479 @@
+//! `rtd tui`, a full-screen interface: the project tree on the left and the
+//! tasks of the selected project on the right.
+//!
+//! Every change goes through the same [`Workspace`] methods as the CLI, so the
+//! files are written the same way, and the list is read again after each one.
+
+use crate::dates::{format_due, today};
+use crate::error::{Error, Result};
+use crate::id::TaskId;
+use crate::query::Query;
+use crate::task::{sort_tasks, Task};
+use crate::workspace::{Filter, Modification, Workspace, INBOX_FNAME};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use speedate::Date;
+use std::path::{Path, PathBuf};
+
+const HELP: &str =
+    "x toggle  a add  m move  @ label  d date  D delete  / filter  tab switch  q quit";
+
+/// A folder or a file of the project tree, relative to the root.
+struct Node {
+    path: PathBuf,
+    depth: usize,
+    is_dir: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Projects,
+    Tasks,
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    Add,
+    Move,
+    Label,
+    Date,
+}
+
+impl Action {
+    fn prompt(self) -> &'static str {
+        match self {
+            Action::Add => "New task",
+            Action::Move => "Move to",
+            Action::Label => "Label",
+            Action::Date => "Date (e.g. fri, +3d, none)",
+        }
+    }
+}
+
+/// What the keys do at the moment.
+enum Mode {
+    Normal,
+    /// Typing the filter, the list follows every key.
+    Filter,
+    /// Typing the argument of an action.
+    Prompt(Action, String),
+    /// Waiting for `y` to remove the task.
+    Confirm(TaskId),
+}
+
+struct App<'a> {
+    ws: &'a Workspace,
+    base: Filter,
+    nodes: Vec<Node>,
+    node_state: ListState,
+    tasks: Vec<(PathBuf, Task)>,
+    task_state: ListState,
+    focus: Focus,
+    mode: Mode,
+    /// A query, see [`Query`].
+    filter: String,
+    message: String,
+    quit: bool,
+}
+
+/// Runs the interface until the user quits. `filter` applies on top of the
+/// filter box, e.g. to hide the tasks that start later.
+pub fn run(ws: &Workspace, filter: Filter) -> Result<()> {
+    let mut app = App {
+        ws,
+        base: filter,
+        nodes: tree(ws)?,
+        node_state: ListState::default().with_selected(Some(0)),
+        tasks: Vec::new(),
+        task_state: ListState::default(),
+        focus: Focus::Tasks,
+        mode: Mode::Normal,
+        filter: String::new(),
+        message: String::new(),
+        quit: false,
+    };
+    app.reload()?;
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// The root, then the folders and files in it, each folder before its files.
+fn tree(ws: &Workspace) -> Result<Vec<Node>> {
+    let mut files: Vec<PathBuf> = ws
+        .files()?
+        .iter()
+        .map(|f| ws.relative(f).to_path_buf())
+        .collect();
+    files.sort();
+    let mut nodes = vec![Node {
+        path: PathBuf::new(),
+        depth: 0,
+        is_dir: true,
+    }];
+    for file in files {
+        let mut dir = PathBuf::new();
+        let folders: Vec<_> = file.parent().into_iter().flat_map(|p| p.iter()).collect();
+        for (depth, folder) in folders.iter().enumerate() {
+            dir.push(folder);
+            if !nodes.iter().any(|n| n.path == dir) {
+                nodes.push(Node {
+                    path: dir.clone(),
+                    depth: depth + 1,
+                    is_dir: true,
+                });
+            }
+        }
+        let depth = folders.len() + 1;
+        nodes.push(Node {
+            path: file,
+            depth,
+            is_dir: false,
+        });
+    }
+    Ok(nodes)
+}
+
+impl App<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.quit {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .map_err(Error::Terminal)?;
+            if let Event::Key(key) = event::read().map_err(Error::Terminal)? {
+                if key.kind == KeyEventKind::Press {
+                    self.on_key(key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&self) -> &Node {
+        &self.nodes[self.node_state.selected().unwrap_or(0)]
+    }
+
+    fn task(&self) -> Option<&Task> {
+        self.task_state
+            .selected()
+            .and_then(|i| self.tasks.get(i))
+            .map(|(_, t)| t)
+    }
+
+    /// Reads the tasks of the selected project again, keeping the selected
+    /// task if it's still there. A filter that doesn't parse keeps the list
+    /// as it is and tells why.
+    fn reload(&mut self) -> Result<()> {
+        let query = if self.filter.trim().is_empty() {
+            None
+        } else {
+            match Query::parse(&self.filter, &today()) {
+                Ok(query) => Some(query),
+                Err(e) => {
+                    self.message = e.to_string().lines().next().unwrap_or("").to_string();
+                    return Ok(());
+                }
+            }
+        };
+        let filter = Filter {
+            query,
+            ..self.base.clone()
+        };
+        let selected = self.task().and_then(|t| t.id()).cloned();
+        let scope = self.node().path.clone();
+        self.tasks.clear();
+        for mut project in self.ws.query(&scope, &filter)? {
+            sort_tasks(&mut project.tasks);
+            let path = self.ws.relative(&project.path).to_path_buf();
+            self.tasks
+                .extend(project.tasks.into_iter().map(|t| (path.clone(), t)));
+        }
+        let index = selected
+            .and_then(|id| self.tasks.iter().position(|(_, t)| t.id() == Some(&id)))
+            .or_else(|| self.task_state.selected())
+            .map(|i| i.min(self.tasks.len().saturating_sub(1)));
+        self.task_state.select(if self.tasks.is_empty() {
+            None
+        } else {
+            index.or(Some(0))
+        });
+        Ok(())
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Result<()> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return Ok(());
+        }
+        match std::mem::replace(&mut self.mode, Mode::Normal) {
+            Mode::Normal => self.on_normal_key(key)?,
+            Mode::Filter => match key.code {
+                KeyCode::Enter => {}
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.reload()?;
+                }
+                code => {
+                    edit(&mut self.filter, code);
+                    self.mode = Mode::Filter;
+                    self.message.clear();
+                    self.reload()?;
+                }
+            },
+            Mode::Prompt(action, mut input) => match key.code {
+                KeyCode::Enter => {
+                    self.message = match self.apply(action, input.trim()) {
+                        Ok(message) => message,
+                        Err(e) => e.to_string(),
+                    };
+                    self.reload()?;
+                }
+                KeyCode::Esc => {}
+                code => {
+                    edit(&mut input, code);
+                    self.mode = Mode::Prompt(action, input);
+                }
+            },
+            Mode::Confirm(task_id) => {
+                if key.code == KeyCode::Char('y') {
+                    self.message = match self.ws.remove_task(&task_id) {
+                        Ok(_) => format!("Task &{} is removed", task_id),
+                        Err(e) => e.to_string(),
+                    };
+                    self.reload()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_normal_key(&mut self, key: KeyEvent) -> Result<()> {
+        let task_id = self.task().and_then(|t| t.id()).cloned();
+        let on_task = self.focus == Focus::Tasks && task_id.is_some();
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Tab | KeyCode::Left | KeyCode::Right | KeyCode::Char('h' | 'l') => {
+                self.focus = match self.focus {
+                    Focus::Projects => Focus::Tasks,
+                    Focus::Tasks => Focus::Projects,
+                };
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.step(1)?,
+            KeyCode::Up | KeyCode::Char('k') => self.step(-1)?,
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Char('a') => self.mode = Mode::Prompt(Action::Add, String::new()),
+            KeyCode::Char('x' | ' ') if on_task => {
+                let toggle = Modification::ToggleStatus {
+                    with_subtasks: false,
+                };
+                let task_id = task_id.expect("Checked above.");
+                self.message = match self.ws.modify_task(&task_id, &toggle) {
+                    Ok(modified) => format!("Task &{} is {} now", task_id, modified.task.status()),
+                    Err(e) => e.to_string(),
+                };
+                self.reload()?;
+            }
+            KeyCode::Char('m') if on_task => self.mode = Mode::Prompt(Action::Move, String::new()),
+            KeyCode::Char('@') if on_task => {
+                self.mode = Mode::Prompt(Action::Label, "@".to_string())
+            }
+            KeyCode::Char('d') if on_task => self.mode = Mode::Prompt(Action::Date, String::new()),
+            KeyCode::Char('D') | KeyCode::Delete if on_task => {
+                self.mode = Mode::Confirm(task_id.expect("Checked above."))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Moves the selection of the focused list.
+    fn step(&mut self, by: isize) -> Result<()> {
+        let (state, len) = match self.focus {
+            Focus::Projects => (&mut self.node_state, self.nodes.len()),
+            Focus::Tasks => (&mut self.task_state, self.tasks.len()),
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        let next = state
+            .selected()
+            .unwrap_or(0)
+            .saturating_add_signed(by)
+            .min(len - 1);
+        state.select(Some(next));
+        if self.focus == Focus::Projects {
+            self.task_state.select(Some(0));
+            self.reload()?;
+        }
+        Ok(())
+    }
+
+    /// Runs the action, returns what to tell the user.
+    fn apply(&self, action: Action, input: &str) -> Result<String> {
+        let task_id = self.task().and_then(|t| t.id()).cloned();
+        match (action, task_id) {
+            (Action::Add, _) => {
+                let node = self.node();
+                let project = if node.is_dir {
+                    Path::new(INBOX_FNAME)
+                } else {
+                    node.path.as_path()
+                };
+                let task = self.ws.add_task(input, project)?;
+                Ok(format!("Added {} to {}", task, project.display()))
+            }
+            (Action::Move, Some(task_id)) => {
+                self.ws.move_task(&task_id, Path::new(input))?;
+                Ok(format!("Task &{} is moved to {}", task_id, input))
+            }
+            (Action::Label, Some(task_id)) => {
+                let modified = self
+                    .ws
+                    .modify_task(&task_id, &Modification::AddLabel(input.to_string()))?;
+                Ok(modified.task.to_string().trim().to_string())
+            }
+            (Action::Date, Some(task_id)) => {
+                let modification = Modification::set_date(input, &today())?;
+                let modified = self.ws.modify_task(&task_id, &modification)?;
+                Ok(modified.task.to_string().trim().to_string())
+            }
+            (_, None) => Ok(String::new()),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, input, status] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
+                .areas(main);
+
+        let focused = self.focus;
+        let block = |title: &'static str, focus: Focus| {
+            let style = if focused == focus {
+                Style::new().fg(Color::Cyan)
+            } else {
+                Style::new()
+            };
+            Block::new()
+                .borders(Borders::ALL)
+                .border_style(style)
+                .title(title)
+        };
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+        let nodes: Vec<ListItem> = self
+            .nodes
+            .iter()
+            .map(|n| {
+                let name = match n.path.file_name() {
+                    None => "All".to_string(),
+                    Some(name) if n.is_dir => format!("{}/", name.to_string_lossy()),
+                    Some(name) => name.to_string_lossy().into_owned(),
+                };
+                ListItem::new(format!("{}{}", "  ".repeat(n.depth), name))
+            })
+            .collect();
+        let nodes = List::new(nodes)
+            .block(block("Projects", Focus::Projects))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(nodes, left, &mut self.node_state);
+
+        let today = today();
+        let show_project = self.node().is_dir;
+        let tasks: Vec<ListItem> = self
+            .tasks
+            .iter()
+            .map(|(path, task)| task_item(task, show_project.then_some(path.as_path()), &today))
+            .collect();
+        let tasks = List::new(tasks)
+            .block(block("Tasks", Focus::Tasks))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(tasks, right, &mut self.task_state);
+
+        let (line, typing) = match &self.mode {
+            Mode::Filter => (format!("/{}", self.filter), true),
+            Mode::Prompt(action, text) => (format!("{}: {}", action.prompt(), text), true),
+            Mode::Confirm(task_id) => (
+                format!("Remove &{} with its subtasks? (y/n)", task_id),
+                false,
+            ),
+            Mode::Normal if self.filter.is_empty() => (String::new(), false),
+            Mode::Normal => (format!("/{}", self.filter), false),
+        };
+        if typing {
+            let x = input.x + line.chars().count() as u16;
+            frame.set_cursor_position(Position::new(x.min(input.right()), input.y));
+        }
+        frame.render_widget(Paragraph::new(line), input);
+        let message = if self.message.is_empty() {
+            HELP
+        } else {
+            &self.message
+        };
+        frame.render_widget(
+            Paragraph::new(message).style(Style::new().fg(Color::DarkGray)),
+            status,
+        );
+    }
+}
+
+/// Typing in the filter box or a prompt.
+fn edit(text: &mut String, code: KeyCode) {
+    match code {
+        KeyCode::Char(c) => text.push(c),
+        KeyCode::Backspace => {
+            text.pop();
+        }
+        _ => {}
+    }
+}
+
+/// The task line coloured the same way as the CLI does it on a terminal.
+fn task_item(task: &Task, project: Option<&Path>, today: &Date) -> ListItem<'static> {
+    let date = task
+        .date()
+        .map(|d| format!("%{}", format_due(d, task.time())));
+    let date_style = match task.date() {
+        _ if task.status().is_closed() => Style::new(),
+        Some(d) if d < today => Style::new().fg(Color::Red),
+        Some(d) if d == today => Style::new().fg(Color::Yellow),
+        _ => Style::new(),
+    };
+    let line = task.line();
+    let body = line.trim_start();
+    let mut spans = vec![Span::raw(line[..line.len() - body.len()].to_string())];
+    for (i, word) in body.split(' ').enumerate() {
+        if i > 0 {
+            spans.push(Span::raw(" "));
+        }
+        let style = if word.starts_with('@') {
+            Style::new().fg(Color::DarkGray)
+        } else if Some(word) == date.as_deref() {
+            date_style
+        } else {
+            Style::new()
+        };
+        spans.push(Span::styled(word.to_string(), style));
+    }
+    if let Some(project) = project {
+        spans.push(Span::styled(
+            format!("  {}", project.display()),
+            Style::new().fg(Color::DarkGray),
+        ));
+    }
+    let mut line = Line::from(spans);
+    if task.status().is_closed() {
+        line = line.style(Style::new().add_modifier(Modifier::CROSSED_OUT));
+    }
+    ListItem::new(line)
+}
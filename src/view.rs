@@ -0,0 +1,442 @@
+//! Saved views, defined in the config:
+//!
+//! ```text
+//! view.today = due<=today and not @someday
+//! view.today.sort = date
+//! view.today.group = label
+//! view.today.format = &{id} {title} ({project})
+//! ```
+//!
+//! The value of `view.<name>` is a [`Query`](crate::query::Query). Tasks can
+//! be sorted by `priority` (the default), `date`, `title` or `file` (the order
+//! they are written in), and grouped by `file` (the default), `label`, `date`
+//! or `none`. The format is `plain` (the task line, the default) or a template
+//! with `{id}`, `{status}`, `{title}`, `{date}`, `{start}`, `{priority}`,
+//! `{labels}`, `{project}` and `{line}` in it. JSON and CSV output is not a
+//! view format, it comes from `--format` like for every other command.
+
+use crate::error::{Error, Result};
+use crate::format::Format;
+use crate::task::{sort_tasks, Task};
+use crate::workspace::Project;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sort {
+    /// Priority, then date, see [`sort_tasks`].
+    #[default]
+    Priority,
+    Date,
+    Title,
+    /// The order of the tasks in their files.
+    File,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Group {
+    #[default]
+    File,
+    /// A task with several labels is in several groups.
+    Label,
+    Date,
+    None,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct View {
+    pub name: String,
+    /// Empty for all tasks.
+    pub query: String,
+    pub sort: Sort,
+    pub group: Group,
+    /// None for plain task lines, otherwise a template.
+    pub format: Option<String>,
+}
+
+/// Tasks under a heading. The heading is None when the tasks are not grouped.
+#[derive(Debug, Clone)]
+pub struct TaskGroup {
+    pub heading: Option<String>,
+    pub tasks: Vec<(PathBuf, Task)>,
+}
+
+impl View {
+    /// The options that differ from the defaults, e.g. `sort: date, group: label`.
+    pub fn options(&self) -> String {
+        let mut options = Vec::new();
+        if self.sort != Sort::default() {
+            options.push(format!("sort: {}", self.sort));
+        }
+        if self.group != Group::default() {
+            options.push(format!("group: {}", self.group));
+        }
+        if let Some(format) = &self.format {
+            options.push(format!("format: {}", format));
+        }
+        options.join(", ")
+    }
+
+    /// Sorts and groups the tasks of `projects`.
+    pub fn arrange(&self, mut projects: Vec<Project>) -> Vec<TaskGroup> {
+        if self.sort == Sort::Priority {
+            for project in projects.iter_mut() {
+                sort_tasks(&mut project.tasks);
+            }
+        }
+        let mut groups: Vec<TaskGroup> = Vec::new();
+        let mut add = |heading: Option<String>, path: &Path, task: &Task| {
+            let entry = (path.to_path_buf(), task.clone());
+            match groups.iter_mut().find(|g| g.heading == heading) {
+                Some(group) => group.tasks.push(entry),
+                None => groups.push(TaskGroup {
+                    heading,
+                    tasks: vec![entry],
+                }),
+            }
+        };
+        for project in projects.iter() {
+            for task in project.tasks.iter() {
+                match self.group {
+                    Group::File => add(
+                        Some(project.path.display().to_string()),
+                        &project.path,
+                        task,
+                    ),
+                    Group::Label if task.labels().is_empty() => {
+                        add(Some("no label".to_string()), &project.path, task)
+                    }
+                    Group::Label => {
+                        for label in task.labels() {
+                            add(Some(label.clone()), &project.path, task);
+                        }
+                    }
+                    Group::Date => {
+                        let heading = task.date().map(|d| d.to_string());
+                        add(
+                            Some(heading.unwrap_or("no date".to_string())),
+                            &project.path,
+                            task,
+                        )
+                    }
+                    Group::None => add(None, &project.path, task),
+                }
+            }
+        }
+        match self.group {
+            // Dates in order, the tasks without one last.
+            Group::Date => groups.sort_by_key(|g| {
+                let heading = g.heading.clone().unwrap_or_default();
+                (heading == "no date", heading)
+            }),
+            Group::Label => groups.sort_by_key(|g| {
+                let heading = g.heading.clone().unwrap_or_default();
+                (heading == "no label", heading)
+            }),
+            Group::File | Group::None => {}
+        }
+        for group in groups.iter_mut() {
+            self.sort_group(&mut group.tasks);
+        }
+        groups
+    }
+
+    fn sort_group(&self, tasks: &mut [(PathBuf, Task)]) {
+        match self.sort {
+            // Tasks of a single file were sorted with their subtasks already.
+            Sort::Priority if self.group == Group::File => {}
+            Sort::Priority => tasks.sort_by_key(|(_, t)| {
+                let priority = t.priority().map(|p| p.level()).unwrap_or(u8::MAX);
+                (priority, t.due_at().is_none(), t.due_at())
+            }),
+            Sort::Date => tasks.sort_by_key(|(_, t)| (t.due_at().is_none(), t.due_at())),
+            Sort::Title => tasks.sort_by_key(|(_, t)| t.title().to_lowercase()),
+            Sort::File => {}
+        }
+    }
+}
+
+/// Fills in a view template for the task in the file at `project`.
+pub fn render(template: &str, task: &Task, project: &Path) -> String {
+    let or_empty = |v: Option<String>| v.unwrap_or_default();
+    template
+        .replace("{id}", &or_empty(task.id().map(|i| i.to_string())))
+        .replace("{status}", &task.status().to_string())
+        .replace("{title}", task.title())
+        .replace("{date}", &or_empty(task.date().map(|d| d.to_string())))
+        .replace("{start}", &or_empty(task.start().map(|d| d.to_string())))
+        .replace(
+            "{priority}",
+            &or_empty(task.priority().map(|p| p.to_string())),
+        )
+        .replace("{labels}", &task.labels().join(" "))
+        .replace("{project}", &project.display().to_string())
+        .replace("{line}", task.line())
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Sort::Priority => "priority",
+            Sort::Date => "date",
+            Sort::Title => "title",
+            Sort::File => "file",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Group::File => "file",
+            Group::Label => "label",
+            Group::Date => "date",
+            Group::None => "none",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Sort, String> {
+        match s {
+            "priority" | "prio" => Ok(Sort::Priority),
+            "date" | "due" => Ok(Sort::Date),
+            "title" => Ok(Sort::Title),
+            "file" | "none" => Ok(Sort::File),
+            _ => Err(format!(
+                "Unknown sort order '{}', use priority, date, title or file.",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for Group {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Group, String> {
+        match s {
+            "file" | "project" => Ok(Group::File),
+            "label" => Ok(Group::Label),
+            "date" => Ok(Group::Date),
+            "none" => Ok(Group::None),
+            _ => Err(format!(
+                "Unknown grouping '{}', use file, label, date or none.",
+                s
+            )),
+        }
+    }
+}
+
+/// Reads a `view.<name>[.sort|.group|.format]` config entry into `views`.
+pub(crate) fn parse_config_entry(views: &mut Vec<View>, key: &str, value: &str) -> Result<()> {
+    let Some(rest) = key.strip_prefix("view.") else {
+        return Ok(());
+    };
+    let (name, option) = match rest.split_once('.') {
+        Some((name, option)) => (name, Some(option)),
+        None => (rest, None),
+    };
+    if name.is_empty() {
+        return Err(Error::Config(format!("A view in '{}' needs a name.", key)));
+    }
+    let view = match views.iter_mut().position(|v| v.name == name) {
+        Some(i) => &mut views[i],
+        None => {
+            views.push(View {
+                name: name.to_string(),
+                ..Default::default()
+            });
+            views.last_mut().expect("Just pushed.")
+        }
+    };
+    match option {
+        None => view.query = value.to_string(),
+        Some("sort") => view.sort = value.parse().map_err(Error::Config)?,
+        Some("group") => view.group = value.parse().map_err(Error::Config)?,
+        Some("format") if value == "plain" => view.format = None,
+        // Output formats are chosen per command, a template can't be one.
+        Some("format") if value.parse::<Format>().is_ok() => {
+            return Err(Error::Config(format!(
+                "'{}' is not a view format, use plain or a template; for {} output run rtd view {} --format {}.",
+                value, value, name, value
+            )))
+        }
+        Some("format") => view.format = Some(value.to_string()),
+        Some(option) => {
+            return Err(Error::Config(format!(
+                "Unknown view option '{}', use sort, group or format.",
+                option
+            )))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::parse_task;
+
+    fn parse(entries: &[(&str, &str)]) -> Result<Vec<View>> {
+        let mut views = Vec::new();
+        for (key, value) in entries {
+            parse_config_entry(&mut views, key, value)?;
+        }
+        Ok(views)
+    }
+
+    #[test]
+    fn parses_config_entries() {
+        let views = parse(&[
+            ("view.today", "due<=today"),
+            ("view.next.sort", "due"),
+            ("view.next", "@next"),
+            ("view.next.group", "label"),
+            ("view.short.format", "&{id} {title}"),
+            ("view.today.format", "plain"),
+            ("editor", "vim"),
+        ])
+        .unwrap();
+        let view = |name: &str, query: &str, sort, group, format: Option<&str>| View {
+            name: name.to_string(),
+            query: query.to_string(),
+            sort,
+            group,
+            format: format.map(str::to_string),
+        };
+        assert_eq!(
+            views,
+            [
+                view("today", "due<=today", Sort::Priority, Group::File, None),
+                view("next", "@next", Sort::Date, Group::Label, None),
+                view(
+                    "short",
+                    "",
+                    Sort::Priority,
+                    Group::File,
+                    Some("&{id} {title}")
+                ),
+            ]
+        );
+        assert_eq!(views[1].options(), "sort: date, group: label");
+        assert_eq!(views[0].options(), "");
+    }
+
+    #[test]
+    fn rejects_broken_config_entries() {
+        for entry in [
+            ("view.", "@next"),
+            ("view..sort", "date"),
+            ("view.next.sort", "size"),
+            ("view.next.group", "priority"),
+            ("view.next.colour", "red"),
+            ("view.next.format", "json"),
+            ("view.next.format", "ndjson"),
+            ("view.next.format", "csv"),
+        ] {
+            assert!(
+                matches!(parse(&[entry]), Err(Error::Config(_))),
+                "{:?}",
+                entry
+            );
+        }
+    }
+
+    fn project(path: &str, lines: &[&str]) -> Project {
+        Project {
+            path: PathBuf::from(path),
+            tasks: lines.iter().map(|l| parse_task(l).unwrap()).collect(),
+        }
+    }
+
+    fn projects() -> Vec<Project> {
+        vec![
+            project(
+                "inbox.md",
+                &[
+                    "- [ ] &1 Write @work %2024-01-25",
+                    "- [ ] &2 buy milk",
+                    "- [ ] &3 Call !1 @home @work %2024-01-20",
+                ],
+            ),
+            project("work.md", &["- [ ] &4 Answer !2 @work"]),
+        ]
+    }
+
+    /// The headings with the ids of their tasks.
+    fn summary(groups: &[TaskGroup]) -> Vec<(Option<&str>, Vec<String>)> {
+        groups
+            .iter()
+            .map(|g| {
+                let ids = g.tasks.iter().map(|(_, t)| t.id().unwrap().to_string());
+                (g.heading.as_deref(), ids.collect())
+            })
+            .collect()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn arranges_by_file_and_priority() {
+        let groups = View::default().arrange(projects());
+        assert_eq!(
+            summary(&groups),
+            [
+                (Some("inbox.md"), ids(&["3", "1", "2"])),
+                (Some("work.md"), ids(&["4"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn arranges_by_label_and_date() {
+        let view = View {
+            sort: Sort::Date,
+            group: Group::Label,
+            ..Default::default()
+        };
+        // A task is under each of its labels, the tasks without one last.
+        assert_eq!(
+            summary(&view.arrange(projects())),
+            [
+                (Some("@home"), ids(&["3"])),
+                (Some("@work"), ids(&["3", "1", "4"])),
+                (Some("no label"), ids(&["2"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn arranges_by_date_and_title() {
+        let view = View {
+            sort: Sort::Title,
+            group: Group::Date,
+            ..Default::default()
+        };
+        assert_eq!(
+            summary(&view.arrange(projects())),
+            [
+                (Some("2024-01-20"), ids(&["3"])),
+                (Some("2024-01-25"), ids(&["1"])),
+                (Some("no date"), ids(&["4", "2"])),
+            ]
+        );
+        let view = View {
+            sort: Sort::File,
+            group: Group::None,
+            ..Default::default()
+        };
+        assert_eq!(
+            summary(&view.arrange(projects())),
+            [(None, ids(&["1", "2", "3", "4"]))]
+        );
+    }
+}
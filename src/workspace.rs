@@ -0,0 +1,1250 @@
+use crate::dates::{add_days, parse_date_time, resolve_dates, to_naive, today};
+use crate::document::Document;
+use crate::error::{Error, Result};
+use crate::history::{History, HISTORY_FNAME};
+use crate::id::{IdMode, TaskId};
+use crate::index::{Index, INDEX_FNAME};
+use crate::query::Query;
+use crate::storage::{self, Transaction, JOURNAL_FNAME, LOCK_FNAME, TMP_SUFFIX};
+use crate::task::{is_valid_label, parse_task, Priority, Task, TaskStatus, TASK_UNDONE};
+use chrono::{NaiveDateTime, NaiveTime};
+use regex::Regex;
+use speedate::Date;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+pub const INBOX_FNAME: &str = "inbox.md";
+pub const DONE_TASKS_FNAME: &str = ".done";
+// SERVICE_FNAMES files will be ignored when iterating over files.
+// They are used by rtd for bookkeeping.
+const SERVICE_FNAMES: [&str; 5] = [
+    DONE_TASKS_FNAME,
+    JOURNAL_FNAME,
+    LOCK_FNAME,
+    INDEX_FNAME,
+    HISTORY_FNAME,
+];
+
+/// Which tasks to return from a query.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only tasks with a date <= today.
+    pub due_only: bool,
+    /// Only tasks due at this moment or earlier.
+    pub due_before: Option<NaiveDateTime>,
+    /// Only tasks having this label (with the @ sign).
+    pub label: Option<String>,
+    /// Only tasks with this status.
+    pub status: Option<TaskStatus>,
+    /// Only tasks with this priority or a more important one.
+    pub min_priority: Option<Priority>,
+    /// Only tasks that start after today (true) or the ones that don't (false).
+    pub deferred: Option<bool>,
+    /// Only tasks matching the query.
+    pub query: Option<Query>,
+}
+
+impl Filter {
+    /// `path` is the path of the task's file relative to the root.
+    pub fn matches(&self, task: &Task, path: &Path, today: &Date) -> bool {
+        if self.due_only && !task.is_due(today) {
+            return false;
+        }
+        if let Some(due_before) = self.due_before {
+            if task.due_at().is_none_or(|d| d > due_before) {
+                return false;
+            }
+        }
+        if let Some(label) = &self.label {
+            if !task.has_label(label) {
+                return false;
+            }
+        }
+        if let Some(status) = self.status {
+            if task.status() != status {
+                return false;
+            }
+        }
+        if let Some(deferred) = self.deferred {
+            if task.is_deferred(today) != deferred {
+                return false;
+            }
+        }
+        if let Some(min_priority) = self.min_priority {
+            // A smaller number is a higher priority.
+            if task.priority().is_none_or(|p| p > min_priority) {
+                return false;
+            }
+        }
+        self.query.as_ref().is_none_or(|q| q.matches(task, path))
+    }
+}
+
+/// A single change to apply to a task.
+#[derive(Debug, Clone)]
+pub enum Modification {
+    AddLabel(String),
+    /// Removes every occurrence of the label.
+    RemoveLabel(String),
+    /// Replaces the words of the title, keeps the labels, dates and the rest.
+    /// The title can only have words in it, see [`Task::set_title`].
+    SetTitle(String),
+    /// Replaces the task line, but not its indentation, with a new one. It has
+    /// to keep the id of the task. Relative dates in it are resolved.
+    SetText(String),
+    /// done -> undone, anything else -> done. Subtasks can get the new status too.
+    ToggleStatus {
+        with_subtasks: bool,
+    },
+    /// Sets the status. Subtasks can get it too.
+    SetStatus {
+        status: TaskStatus,
+        with_subtasks: bool,
+    },
+    /// If the date is set, it is removed, otherwise the task becomes due today.
+    ToggleDate,
+    /// A date of None removes the date. The task keeps its time unless it
+    /// gets a new one.
+    SetDate {
+        date: Option<Date>,
+        time: Option<NaiveTime>,
+    },
+    /// None removes the priority.
+    SetPriority(Option<Priority>),
+}
+
+impl Modification {
+    /// Sets the date written as `rtd date` takes it, e.g. `tomorrow` or
+    /// `friT14:30`; `none` removes the date.
+    pub fn set_date(when: &str, today: &Date) -> Result<Modification> {
+        let due = match when {
+            "none" => None,
+            w => Some(parse_date_time(w, today).ok_or_else(|| Error::InvalidDate(w.to_string()))?),
+        };
+        Ok(Modification::SetDate {
+            date: due.as_ref().map(|(d, _)| d.clone()),
+            time: due.and_then(|(_, t)| t),
+        })
+    }
+}
+
+/// A change to a task in a batch, see [`Workspace::apply`].
+#[derive(Debug, Clone)]
+pub enum Change {
+    Modify(Modification),
+    /// Moves the task with its subtasks to the end of this project, relative
+    /// to the root.
+    Move(PathBuf),
+    /// Removes the task with its subtasks.
+    Remove,
+}
+
+/// Tasks of a single project file.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub path: PathBuf,
+    pub tasks: Vec<Task>,
+}
+
+/// The result of [`Workspace::modify_task`], [`Workspace::move_task`],
+/// [`Workspace::remove_task`] and [`Workspace::apply`].
+#[derive(Debug, Clone)]
+pub struct Modified {
+    /// The file the task is in (or was removed from).
+    pub path: PathBuf,
+    /// The new state of the task.
+    pub task: Task,
+    /// The next instance of a recurring task that was just done.
+    pub next: Option<Task>,
+}
+
+/// A task that got a new id from [`Workspace::assign_ids`].
+#[derive(Debug, Clone)]
+pub struct IdChange {
+    pub path: PathBuf,
+    /// None if the task had no id, otherwise the id it shared with another task.
+    pub old: Option<TaskId>,
+    pub task: Task,
+}
+
+/// An rtd root directory: a tree of markdown files with tasks in them.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    root: PathBuf,
+    id_mode: IdMode,
+    archive_cancelled: bool,
+}
+
+impl Workspace {
+    /// Opens a workspace rooted at `root`. The directory has to exist.
+    /// If the last run crashed in the middle of a write, the write is finished here.
+    pub fn open(root: impl AsRef<Path>) -> Result<Workspace> {
+        let root = root.as_ref().to_path_buf();
+        if !root.is_dir() {
+            return Err(Error::ProjectNotFound(root));
+        }
+        storage::recover(&root)?;
+        Ok(Workspace {
+            root,
+            id_mode: IdMode::default(),
+            archive_cancelled: true,
+        })
+    }
+
+    /// Sets how ids of new tasks are made.
+    pub fn with_id_mode(mut self, id_mode: IdMode) -> Workspace {
+        self.id_mode = id_mode;
+        self
+    }
+
+    /// Sets whether `archive` moves cancelled tasks too, it does by default.
+    pub fn with_archive_cancelled(mut self, archive_cancelled: bool) -> Workspace {
+        self.archive_cancelled = archive_cancelled;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn inbox_path(&self) -> PathBuf {
+        self.root.join(INBOX_FNAME)
+    }
+
+    pub fn done_path(&self) -> PathBuf {
+        self.root.join(DONE_TASKS_FNAME)
+    }
+
+    fn history_path(&self) -> PathBuf {
+        self.root.join(HISTORY_FNAME)
+    }
+
+    /// When the tasks last changed and when the last review was.
+    pub fn history(&self) -> Result<History> {
+        History::load(&self.root)
+    }
+
+    /// Creates the inbox and the archive if they are missing.
+    /// Returns the paths of the files that were created.
+    pub fn ensure_layout(&self) -> Result<Vec<PathBuf>> {
+        let mut created = Vec::new();
+        for path in [self.inbox_path(), self.done_path()] {
+            if !path.exists() {
+                File::create(&path).map_err(|e| Error::io(&path, e))?;
+                created.push(path);
+            }
+        }
+        Ok(created)
+    }
+
+    /// All project files in the workspace.
+    pub fn files(&self) -> Result<Vec<PathBuf>> {
+        self.files_in(&self.root)
+    }
+
+    /// All project files under `dir`. If `dir` is a file, just that file.
+    pub fn files_in(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let dir = self.root.join(dir);
+        if dir.is_file() {
+            return Ok(vec![dir]);
+        }
+        if !dir.is_dir() {
+            return Err(Error::ProjectNotFound(dir));
+        }
+        let mut all_files: Vec<PathBuf> = Vec::new();
+        let mut dirs = vec![dir];
+        while let Some(current_dir) = dirs.pop() {
+            let entries = fs::read_dir(&current_dir).map_err(|e| Error::io(&current_dir, e))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if !self.is_service_file(&path) {
+                    all_files.push(path);
+                }
+            }
+        }
+        Ok(all_files)
+    }
+
+    /// The file of `project` (relative to the root) as [`Workspace::files`]
+    /// names it, so that `work/../inbox.md` is `inbox.md`. Files outside of
+    /// the root and service files are not projects.
+    fn project_file(&self, project: &Path) -> Result<PathBuf> {
+        let not_found = || Error::ProjectNotFound(self.root.join(project));
+        let root = self
+            .root
+            .canonicalize()
+            .map_err(|e| Error::io(&self.root, e))?;
+        let path = self
+            .root
+            .join(project)
+            .canonicalize()
+            .map_err(|_| not_found())?;
+        let relative = path.strip_prefix(&root).map_err(|_| not_found())?;
+        let path = self.root.join(relative);
+        if !path.is_file() || self.is_service_file(&path) {
+            return Err(not_found());
+        }
+        Ok(path)
+    }
+
+    fn is_service_file(&self, path: &Path) -> bool {
+        SERVICE_FNAMES.iter().any(|el| path == self.root.join(el))
+            || path.to_string_lossy().ends_with(TMP_SUFFIX)
+    }
+
+    /// Path relative to the workspace root, for printing.
+    pub fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// Tasks of a single file, in the order they appear.
+    pub fn file_tasks(&self, fpath: &Path) -> Result<Vec<Task>> {
+        Ok(read_document(fpath)?.tasks().map(|(_, t)| t).collect())
+    }
+
+    /// Files under `scope` and the index brought up to date with them.
+    fn index(&self, scope: &Path) -> Result<(Vec<PathBuf>, Index)> {
+        let files = self.files_in(scope)?;
+        let index_path = self.root.join(INDEX_FNAME);
+        let mut index = Index::load(&index_path);
+        let mut changed = index.refresh(&files)?;
+        // Only a walk over the whole workspace knows which files are gone.
+        if self.root.join(scope) == self.root {
+            changed |= index.prune(&files);
+        }
+        if changed {
+            index.save(&index_path)?;
+        }
+        Ok((files, index))
+    }
+
+    /// Tasks matching `filter` in all files under `scope` (a folder or a file,
+    /// relative to the root). Files without matching tasks are skipped.
+    pub fn query(&self, scope: &Path, filter: &Filter) -> Result<Vec<Project>> {
+        let today = today();
+        let (files, index) = self.index(scope)?;
+        let mut projects = Vec::new();
+        for path in files {
+            let Some(file) = index.get(&path) else {
+                continue;
+            };
+            let relative = self.relative(&path);
+            let tasks: Vec<Task> = file
+                .tasks()
+                .map(|(_, t)| t)
+                .filter(|t| filter.matches(t, relative, &today))
+                .collect();
+            if !tasks.is_empty() {
+                projects.push(Project { path, tasks });
+            }
+        }
+        Ok(projects)
+    }
+
+    /// Tasks matching `filter` whose title, labels or notes match `pattern`.
+    /// With `with_archive`, the archived tasks are searched too and come last,
+    /// under the path of the archive.
+    pub fn search(
+        &self,
+        pattern: &Regex,
+        filter: &Filter,
+        with_archive: bool,
+    ) -> Result<Vec<Project>> {
+        let found = |task: &Task, title: &str| {
+            pattern.is_match(title)
+                || task.labels().iter().any(|l| pattern.is_match(l))
+                || task.notes().iter().any(|n| pattern.is_match(n))
+        };
+        let mut projects = self.query(Path::new(""), filter)?;
+        for project in projects.iter_mut() {
+            project.tasks.retain(|t| found(t, t.title()));
+        }
+        let done_path = self.done_path();
+        if with_archive && done_path.exists() {
+            let today = today();
+            let root = self.root.display().to_string();
+            let tasks: Vec<Task> = read_document(&done_path)?
+                .tasks()
+                .map(|(_, t)| t)
+                .filter(|t| {
+                    // Archived tasks end with the path of the file they came from.
+                    let title = match t.title().rsplit_once(' ') {
+                        Some((title, path)) if path.starts_with(&root) => title,
+                        _ => t.title(),
+                    };
+                    found(t, title) && filter.matches(t, Path::new(DONE_TASKS_FNAME), &today)
+                })
+                .collect();
+            projects.push(Project {
+                path: done_path,
+                tasks,
+            });
+        }
+        projects.retain(|p| !p.tasks.is_empty());
+        Ok(projects)
+    }
+
+    pub fn get_task(&self, task_id: &TaskId) -> Result<Task> {
+        self.get_task_with_path(task_id).map(|(_, task)| task)
+    }
+
+    /// The task with the file it is in.
+    pub fn get_task_with_path(&self, task_id: &TaskId) -> Result<(PathBuf, Task)> {
+        let (files, index) = self.index(&self.root)?;
+        for fpath in files {
+            let found = index
+                .get(&fpath)
+                .filter(|f| task_id.as_number().is_none_or(|n| f.max_id >= n))
+                .and_then(|f| f.tasks().find(|(_, t)| t.id() == Some(task_id)));
+            if let Some((_, task)) = found {
+                return Ok((fpath, task));
+            }
+        }
+        Err(Error::TaskNotFound(task_id.clone()))
+    }
+
+    /// Every label with the number of tasks having it, the most used first.
+    pub fn labels(&self) -> Result<Vec<(String, usize)>> {
+        let (files, index) = self.index(&self.root)?;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for f in files.iter().filter_map(|p| index.get(p)) {
+            for (_, task) in f.tasks() {
+                let labels: HashSet<&String> = task.labels().iter().collect();
+                for label in labels {
+                    *counts.entry(label.clone()).or_default() += 1;
+                }
+            }
+        }
+        let mut labels: Vec<(String, usize)> = counts.into_iter().collect();
+        labels.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(labels)
+    }
+
+    /// Renames the label in every task of every project, or removes it if
+    /// `new` is None. The archive is left as it was. Returns the changed tasks
+    /// by file; with `dry_run` nothing is written, and they are what the
+    /// tasks would become.
+    pub fn rewrite_label(
+        &self,
+        old: &str,
+        new: Option<&str>,
+        dry_run: bool,
+    ) -> Result<Vec<Project>> {
+        for label in std::iter::once(old).chain(new) {
+            if !is_valid_label(label) {
+                return Err(Error::InvalidLabel(label.to_string()));
+            }
+        }
+        let (files, index) = self.index(&self.root)?;
+        let mut history = self.history()?;
+        let mut transaction = Transaction::new(&self.root);
+        let mut changed = Vec::new();
+        // The index knows which files have the label, the others aren't read.
+        for fpath in files.into_iter().filter(|p| {
+            index
+                .get(p)
+                .is_some_and(|f| f.labels.iter().any(|l| l == old))
+        }) {
+            let mut doc = read_document(&fpath)?;
+            let mut tasks = Vec::new();
+            for (i, mut task) in doc.tasks().collect::<Vec<_>>() {
+                if !task.has_label(old) {
+                    continue;
+                }
+                match new {
+                    Some(new) => task.rename_label(old, new),
+                    None => task.remove_label(old),
+                }
+                doc.set_line(i, task.line());
+                history.touch(&task, &today());
+                tasks.push(task);
+            }
+            if !tasks.is_empty() {
+                transaction.write(&fpath, doc.render());
+                changed.push(Project { path: fpath, tasks });
+            }
+        }
+        if !dry_run && !changed.is_empty() {
+            transaction.write(&self.history_path(), history.render());
+            transaction.commit()?;
+        }
+        Ok(changed)
+    }
+
+    pub fn max_id(&self) -> Result<i32> {
+        let (files, index) = self.index(&self.root)?;
+        Ok(files
+            .iter()
+            .filter_map(|p| index.get(p))
+            .map(|f| f.max_id)
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// All task ids in the workspace.
+    pub fn ids(&self) -> Result<HashSet<TaskId>> {
+        let (files, index) = self.index(&self.root)?;
+        Ok(files
+            .iter()
+            .filter_map(|p| index.get(p))
+            .flat_map(|f| f.tasks().filter_map(|(_, t)| t.id().cloned()))
+            .collect())
+    }
+
+    /// Makes an id that is not in `taken` and adds it there.
+    fn new_id(&self, seed: &str, max_id: &mut i32, taken: &mut HashSet<TaskId>) -> TaskId {
+        let id = match self.id_mode {
+            IdMode::Number => {
+                *max_id += 1;
+                TaskId::Number(*max_id)
+            }
+            IdMode::Hash => TaskId::generate(seed, taken),
+        };
+        taken.insert(id.clone());
+        id
+    }
+
+    /// Gives an id to every task that doesn't have one, and a new id to every
+    /// task that shares its id with another one. That happens when two devices
+    /// add tasks while out of sync.
+    /// The index tells which files need new ids, only those are read and
+    /// written, all in a single transaction.
+    pub fn assign_ids(&self) -> Result<Vec<IdChange>> {
+        let (mut files, index) = self.index(&self.root)?;
+        // Sorted, so that all devices agree which of the duplicates keeps its id.
+        files.sort();
+        let mut max_id = 0;
+        let mut taken: HashSet<TaskId> = HashSet::new();
+        let mut needs_ids: HashSet<&PathBuf> = HashSet::new();
+        for fpath in files.iter() {
+            let Some(file) = index.get(fpath) else {
+                continue;
+            };
+            max_id = max_id.max(file.max_id);
+            for (_, task) in file.tasks() {
+                if task.id().is_none_or(|id| !taken.insert(id.clone())) {
+                    needs_ids.insert(fpath);
+                }
+            }
+        }
+        if needs_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut seen: HashSet<TaskId> = HashSet::new();
+        let mut changes: Vec<IdChange> = Vec::new();
+        let mut transaction = Transaction::new(&self.root);
+        for fpath in files.iter() {
+            if !needs_ids.contains(fpath) {
+                let ids = index.get(fpath).into_iter().flat_map(|f| f.tasks());
+                seen.extend(ids.filter_map(|(_, t)| t.id().cloned()));
+                continue;
+            }
+            let mut doc = read_document(fpath)?;
+            let before = changes.len();
+            for (i, mut task) in doc.tasks().collect::<Vec<_>>() {
+                let old = task.id().cloned();
+                if let Some(id) = &old {
+                    if seen.insert(id.clone()) {
+                        continue;
+                    }
+                }
+                let id = self.new_id(task.line(), &mut max_id, &mut taken);
+                seen.insert(id.clone());
+                task.set_id(&id);
+                doc.set_line(i, task.line());
+                changes.push(IdChange {
+                    path: fpath.clone(),
+                    old,
+                    task,
+                });
+            }
+            if changes.len() > before {
+                transaction.write(fpath, doc.render());
+            }
+        }
+        if transaction.is_empty() {
+            return Ok(changes);
+        }
+        let mut history = self.history()?;
+        for change in changes.iter() {
+            history.touch(&change.task, &today());
+        }
+        transaction.write(&self.history_path(), history.render());
+        transaction.commit()?;
+        Ok(changes)
+    }
+
+    /// Adds a task to the top of `project` (relative to the root).
+    /// Dates like `%tomorrow` are turned into YYYY-MM-DD.
+    pub fn add_task(&self, description: &str, project: &Path) -> Result<Task> {
+        let fpath = self.root.join(project);
+        if !fpath.is_file() {
+            return Err(Error::ProjectNotFound(fpath));
+        }
+        let task_string = format!("{} {}", TASK_UNDONE, resolve_dates(description, &today()));
+        let mut task =
+            parse_task(&task_string).ok_or_else(|| Error::InvalidTask(description.to_string()))?;
+        if let Some(label) = task.labels().iter().find(|l| !is_valid_label(l)) {
+            return Err(Error::InvalidLabel(label.clone()));
+        }
+        let id = self.new_id(task.line(), &mut self.max_id()?, &mut self.ids()?);
+        task.set_id(&id);
+
+        let mut doc = read_document(&fpath)?;
+        doc.insert(0, task.line());
+        let mut history = self.history()?;
+        history.touch(&task, &today());
+        let mut transaction = Transaction::new(&self.root);
+        transaction.write(&fpath, doc.render());
+        transaction.write(&self.history_path(), history.render());
+        transaction.commit()?;
+        task.set_line_number(1);
+        Ok(task)
+    }
+
+    /// Applies `modification` to the task and returns its new state.
+    /// When a recurring task gets done, its next instance is added above it.
+    pub fn modify_task(&self, task_id: &TaskId, modification: &Modification) -> Result<Modified> {
+        self.apply_one(task_id, Change::Modify(modification.clone()))
+    }
+
+    /// Removes the task together with its subtasks.
+    pub fn remove_task(&self, task_id: &TaskId) -> Result<Modified> {
+        self.apply_one(task_id, Change::Remove)
+    }
+
+    /// Moves a task with its subtasks to the end of `dest` (relative to the root).
+    /// A subtask becomes a top level task there.
+    pub fn move_task(&self, task_id: &TaskId, dest: &Path) -> Result<Modified> {
+        self.apply_one(task_id, Change::Move(dest.to_path_buf()))
+    }
+
+    fn apply_one(&self, task_id: &TaskId, change: Change) -> Result<Modified> {
+        let mut modified = self.apply(&[(task_id.clone(), change)])?;
+        Ok(modified.remove(0))
+    }
+
+    /// Makes the changes one after another and writes the files at the end in
+    /// a single transaction, so either all of them are made or none is. A
+    /// change sees the ones before it, e.g. a task can be labelled and then
+    /// moved. Returns the results in the same order as the changes.
+    pub fn apply(&self, changes: &[(TaskId, Change)]) -> Result<Vec<Modified>> {
+        for (_, change) in changes {
+            if let Change::Modify(
+                Modification::AddLabel(label) | Modification::RemoveLabel(label),
+            ) = change
+            {
+                if !is_valid_label(label) {
+                    return Err(Error::InvalidLabel(label.clone()));
+                }
+            }
+        }
+        let files = self.files()?;
+        let mut docs = Documents::default();
+        // Read when a recurring task needs new ids, and kept for the next ones.
+        let mut ids = None;
+        let mut results = Vec::new();
+        // The tasks whose lines changed, for the history.
+        let mut touched: Vec<Task> = Vec::new();
+        for (task_id, change) in changes {
+            let (fpath, i, mut task) = docs.find(&files, task_id)?;
+            let modified = match change {
+                Change::Modify(modification) => {
+                    let doc = docs.get_mut(&fpath);
+                    let was_done = task.is_done();
+                    let before = task.line().to_string();
+                    modify(doc, i, &mut task, modification)?;
+                    if task.line() != before {
+                        touched.push(task.clone());
+                    }
+                    doc.set_line(i, task.line());
+                    let next = match task.recurrence() {
+                        Some(recurrence) if task.is_done() && !was_done => {
+                            let date = recurrence
+                                .next(task.date(), &today())
+                                .ok_or_else(|| Error::DateOutOfRange(task_id.clone()))?;
+                            let (max_id, taken) = match &mut ids {
+                                Some(ids) => ids,
+                                None => ids.insert((self.max_id()?, self.ids()?)),
+                            };
+                            let len = doc.len();
+                            let next = self
+                                .insert_next_instance(doc, i, date, max_id, taken)
+                                .ok_or_else(|| Error::DateOutOfRange(task_id.clone()))?;
+                            task.set_line_number(i + 1 + doc.len() - len);
+                            Some(next)
+                        }
+                        _ => None,
+                    };
+                    Modified {
+                        path: fpath,
+                        task,
+                        next,
+                    }
+                }
+                Change::Move(dest) => {
+                    let dest_path = self.project_file(dest)?;
+                    if fpath != dest_path {
+                        let block = docs.get_mut(&fpath).remove_block(i);
+                        let dest_doc = docs.load(&dest_path)?;
+                        dest_doc.extend(&block);
+                        let at = dest_doc.len() - block.len();
+                        task = dest_doc
+                            .tasks()
+                            .find(|(j, _)| *j == at)
+                            .map_or(task, |(_, moved)| moved);
+                        touched.push(task.clone());
+                    }
+                    Modified {
+                        path: dest_path,
+                        task,
+                        next: None,
+                    }
+                }
+                Change::Remove => {
+                    docs.get_mut(&fpath).remove_block(i);
+                    Modified {
+                        path: fpath,
+                        task,
+                        next: None,
+                    }
+                }
+            };
+            results.push(modified);
+        }
+        let old_history = self.history()?;
+        let mut history = old_history.clone();
+        for task in touched
+            .iter()
+            .chain(results.iter().filter_map(|m| m.next.as_ref()))
+        {
+            history.touch(task, &today());
+        }
+        // Only what really changed is written, a change that changes nothing
+        // (e.g. done -> done) leaves the files and their mtimes alone.
+        let mut transaction = Transaction::new(&self.root);
+        for (path, doc) in docs.changed() {
+            transaction.write(path, doc.render());
+        }
+        if history != old_history {
+            transaction.write(&self.history_path(), history.render());
+        }
+        transaction.commit()?;
+        Ok(results)
+    }
+
+    /// Copies the block of the task at line `i` above it, with all the tasks
+    /// in it undone and given new ids, and the `date` on the first one. Its
+    /// start date moves as much as its date did. None, with the document
+    /// untouched, if the start date would move past year 9999.
+    fn insert_next_instance(
+        &self,
+        doc: &mut Document,
+        i: usize,
+        date: Date,
+        max_id: &mut i32,
+        taken: &mut HashSet<TaskId>,
+    ) -> Option<Task> {
+        let mut lines: Vec<String> = Vec::new();
+        for j in i..doc.block_end(i) {
+            let line = doc.line(j);
+            match parse_task(line) {
+                Some(mut task) => {
+                    task.set_status(TaskStatus::Todo);
+                    task.set_id(&self.new_id(line, max_id, taken));
+                    if j == i {
+                        if let (Some(start), Some(old)) = (task.start(), task.date()) {
+                            let shift = (to_naive(&date) - to_naive(old)).num_days();
+                            task.set_start(Some(add_days(start, shift)?));
+                        }
+                        task.set_date(Some(date.clone()));
+                    }
+                    lines.push(task.line().to_string());
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+        for (k, line) in lines.iter().enumerate() {
+            doc.insert(i + k, line);
+        }
+        let mut next = parse_task(&lines[0]).expect("The first line of a block is the task.");
+        next.set_line_number(i + 1);
+        Some(next)
+    }
+
+    /// Moves done (and, unless configured otherwise, cancelled) tasks to the
+    /// archive, together with their subtasks. Returns the archived tasks
+    /// (without the subtasks) with the files they were in.
+    pub fn archive(&self) -> Result<Vec<Project>> {
+        let done_path = self.done_path();
+        let mut done_doc = if done_path.exists() {
+            read_document(&done_path)?
+        } else {
+            Document::default()
+        };
+
+        let mut transaction = Transaction::new(&self.root);
+        let mut archived = Vec::new();
+        for fpath in self.files()? {
+            let mut doc = read_document(&fpath)?;
+            let mut blocks: Vec<(usize, Task)> = Vec::new();
+            let mut archived_until = 0;
+            for (i, task) in doc.tasks() {
+                // Subtasks of an archived task go together with it.
+                let archived_status = match task.status() {
+                    TaskStatus::Done => true,
+                    TaskStatus::Cancelled => self.archive_cancelled,
+                    _ => false,
+                };
+                if archived_status && i >= archived_until {
+                    archived_until = doc.block_end(i);
+                    blocks.push((i, task));
+                }
+            }
+            if blocks.is_empty() {
+                continue;
+            }
+            // Going backwards, so that removing a block doesn't shift the next ones.
+            let mut removed: Vec<Vec<String>> = blocks
+                .iter()
+                .rev()
+                .map(|(i, _)| doc.remove_block(*i))
+                .collect();
+            removed.reverse();
+            for mut lines in removed {
+                lines[0] = format!("{} {}", lines[0], fpath.display());
+                done_doc.extend(&lines);
+            }
+            transaction.write(&fpath, doc.render());
+            archived.push(Project {
+                path: fpath,
+                tasks: blocks.into_iter().map(|(_, t)| t).collect(),
+            });
+        }
+        if archived.is_empty() {
+            return Ok(Vec::new());
+        }
+        transaction.write(&done_path, done_doc.render());
+        transaction.commit()?;
+        Ok(archived)
+    }
+}
+
+/// The files a batch of changes has read, with the content they had then.
+#[derive(Default)]
+struct Documents {
+    docs: Vec<(PathBuf, Document, String)>,
+}
+
+impl Documents {
+    fn position(&self, path: &Path) -> Option<usize> {
+        self.docs.iter().position(|(p, _, _)| p == path)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<usize> {
+        if let Some(i) = self.position(path) {
+            return Ok(i);
+        }
+        let doc = read_document(path)?;
+        let content = doc.render();
+        self.docs.push((path.to_path_buf(), doc, content));
+        Ok(self.docs.len() - 1)
+    }
+
+    /// The document of `path`, read from the disk the first time.
+    fn load(&mut self, path: &Path) -> Result<&mut Document> {
+        let i = self.read(path)?;
+        Ok(&mut self.docs[i].1)
+    }
+
+    /// A document that [`Documents::find`] has already read.
+    fn get_mut(&mut self, path: &Path) -> &mut Document {
+        let i = self.position(path).expect("The document has been read.");
+        &mut self.docs[i].1
+    }
+
+    /// Finds the task in `files` and returns its file and line.
+    fn find(&mut self, files: &[PathBuf], task_id: &TaskId) -> Result<(PathBuf, usize, Task)> {
+        for fpath in files {
+            let i = self.read(fpath)?;
+            let doc = &self.docs[i].1;
+            if let Some((i, task)) = doc.tasks().find(|(_, t)| t.id() == Some(task_id)) {
+                return Ok((fpath.clone(), i, task));
+            }
+        }
+        Err(Error::TaskNotFound(task_id.clone()))
+    }
+
+    /// The documents that differ from what was read.
+    fn changed(&self) -> impl Iterator<Item = (&Path, &Document)> {
+        self.docs
+            .iter()
+            .filter(|(_, doc, content)| doc.render() != *content)
+            .map(|(path, doc, _)| (path.as_path(), doc))
+    }
+}
+
+/// Applies a [`Modification`] to the task at line `i` of `doc`, except for
+/// its line, which the caller writes back.
+fn modify(
+    doc: &mut Document,
+    i: usize,
+    task: &mut Task,
+    modification: &Modification,
+) -> Result<()> {
+    match modification {
+        Modification::AddLabel(label) => task.add_label(label),
+        Modification::RemoveLabel(label) => task.remove_label(label),
+        Modification::SetTitle(title) => {
+            // Relative dates are resolved, so that `%tomorrow` is a date here too.
+            if !task.set_title(&resolve_dates(title, &today())) {
+                return Err(Error::InvalidTitle(title.clone()));
+            }
+        }
+        Modification::SetText(text) => {
+            let old_id = task.id().cloned();
+            let mut edited = task.clone();
+            if !edited.set_text(&resolve_dates(text, &today())) {
+                return Err(Error::InvalidTask(text.clone()));
+            }
+            if let Some(label) = edited.labels().iter().find(|l| !is_valid_label(l)) {
+                return Err(Error::InvalidLabel(label.clone()));
+            }
+            if let Some(old_id) = old_id.filter(|id| edited.id() != Some(id)) {
+                return Err(Error::IdChanged(old_id));
+            }
+            *task = edited;
+        }
+        Modification::ToggleStatus { with_subtasks } => {
+            let status = if task.is_done() {
+                TaskStatus::Todo
+            } else {
+                TaskStatus::Done
+            };
+            set_status(doc, i, task, status, *with_subtasks);
+        }
+        Modification::SetStatus {
+            status,
+            with_subtasks,
+        } => set_status(doc, i, task, *status, *with_subtasks),
+        Modification::ToggleDate => match task.date() {
+            Some(_) => task.set_date(None),
+            None => task.set_date(Some(today())),
+        },
+        Modification::SetDate { date, time } => {
+            task.set_date(date.clone());
+            if time.is_some() {
+                task.set_time(*time);
+            }
+        }
+        Modification::SetPriority(priority) => task.set_priority(*priority),
+    }
+    Ok(())
+}
+
+fn set_status(
+    doc: &mut Document,
+    i: usize,
+    task: &mut Task,
+    status: TaskStatus,
+    with_subtasks: bool,
+) {
+    task.set_status(status);
+    if with_subtasks {
+        for (j, mut subtask) in doc.subtasks(i).collect::<Vec<_>>() {
+            subtask.set_status(status);
+            doc.set_line(j, subtask.line());
+        }
+    }
+}
+
+fn read_document(path: &Path) -> Result<Document> {
+    fs::read_to_string(path)
+        .map(|content| Document::parse(&content))
+        .map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempRoot;
+
+    /// The file, the old id and the new id of every change.
+    fn summary(root: &TempRoot, changes: &[IdChange]) -> Vec<(String, Option<String>, String)> {
+        changes
+            .iter()
+            .map(|c| {
+                let path = c.path.strip_prefix(root.path()).unwrap();
+                (
+                    path.display().to_string(),
+                    c.old.as_ref().map(|id| id.to_string()),
+                    c.task.id().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn change(path: &str, old: Option<&str>, new: &str) -> (String, Option<String>, String) {
+        (path.to_string(), old.map(str::to_string), new.to_string())
+    }
+
+    #[test]
+    fn gives_ids_after_the_largest_one() {
+        let root = TempRoot::new(&[
+            ("inbox.md", "# Inbox\n- [ ] &3 A\n- [ ] B\n  - [x] C\n"),
+            ("work.md", "- [ ] &7 D\n"),
+        ]);
+        let ws = Workspace::open(root.path()).unwrap();
+        let changes = ws.assign_ids().unwrap();
+        assert_eq!(
+            summary(&root, &changes),
+            [change("inbox.md", None, "8"), change("inbox.md", None, "9")]
+        );
+        assert_eq!(
+            root.read("inbox.md"),
+            "# Inbox\n- [ ] &3 A\n- [ ] &8 B\n  - [x] &9 C\n"
+        );
+        assert_eq!(root.read("work.md"), "- [ ] &7 D\n");
+        let history = ws.history().unwrap();
+        assert!(history.changed(&TaskId::Number(8)).is_some());
+        assert!(history.changed(&TaskId::Number(3)).is_none());
+        // Everything has an id now.
+        assert!(ws.assign_ids().unwrap().is_empty());
+    }
+
+    #[test]
+    fn renumbers_duplicates_in_later_files() {
+        let root = TempRoot::new(&[
+            ("z.md", "- [ ] &2 C\n- [ ] &1 D\n- [ ] &1 E\n"),
+            ("a/b.md", "- [ ] &1 A\n- [ ] &2 B\n"),
+            ("inbox.md", "- [ ] &5 X\n- [ ] &5 Y\n"),
+        ]);
+        let ws = Workspace::open(root.path()).unwrap();
+        let changes = ws.assign_ids().unwrap();
+        // a/b.md comes first whatever order the files were made in, and the
+        // first task with an id in a file keeps it.
+        assert_eq!(
+            summary(&root, &changes),
+            [
+                change("inbox.md", Some("5"), "6"),
+                change("z.md", Some("2"), "7"),
+                change("z.md", Some("1"), "8"),
+                change("z.md", Some("1"), "9"),
+            ]
+        );
+        assert_eq!(root.read("a/b.md"), "- [ ] &1 A\n- [ ] &2 B\n");
+        assert_eq!(root.read("inbox.md"), "- [ ] &5 X\n- [ ] &6 Y\n");
+        assert_eq!(root.read("z.md"), "- [ ] &7 C\n- [ ] &8 D\n- [ ] &9 E\n");
+    }
+
+    #[test]
+    fn writes_nothing_when_every_id_is_unique() {
+        let root = TempRoot::new(&[("inbox.md", "- [ ] &1 A\n"), ("work.md", "- [ ] &2 B\n")]);
+        let ws = Workspace::open(root.path()).unwrap();
+        assert!(ws.assign_ids().unwrap().is_empty());
+        assert!(!root.join(HISTORY_FNAME).exists());
+        assert!(!root.join(JOURNAL_FNAME).exists());
+    }
+
+    #[test]
+    fn hash_ids() {
+        let root = TempRoot::new(&[
+            ("inbox.md", "- [ ] &k3x7a2q A\n- [ ] B\n- [ ] C\n"),
+            ("work.md", "- [ ] &k3x7a2q D\n- [ ] &4 E\n"),
+        ]);
+        let ws = Workspace::open(root.path())
+            .unwrap()
+            .with_id_mode(IdMode::Hash);
+        let changes = ws.assign_ids().unwrap();
+        let old: Vec<Option<&TaskId>> = changes.iter().map(|c| c.old.as_ref()).collect();
+        let dup = TaskId::Hash("k3x7a2q".to_string());
+        assert_eq!(old, [None, None, Some(&dup)]);
+        let mut new: Vec<&TaskId> = changes.iter().map(|c| c.task.id().unwrap()).collect();
+        assert!(new.iter().all(|id| matches!(id, TaskId::Hash(_))));
+        new.sort();
+        new.dedup();
+        assert_eq!(new.len(), 3);
+        assert!(!new.contains(&&dup));
+        // The new ids are in the files and read back as the same ids.
+        let ids = ws.ids().unwrap();
+        assert_eq!(ids.len(), 5);
+        assert!(new.iter().all(|id| ids.contains(id)));
+    }
+
+    fn mtime(root: &TempRoot, path: &str) -> std::time::SystemTime {
+        fs::metadata(root.join(path)).unwrap().modified().unwrap()
+    }
+
+    #[test]
+    fn no_op_changes_write_nothing() {
+        let root = TempRoot::new(&[(
+            "inbox.md",
+            "- [x] &7 A
+- [ ] &8 B @x
+",
+        )]);
+        let ws = Workspace::open(root.path()).unwrap();
+        let remove_label = Change::Modify(Modification::RemoveLabel("@x".to_string()));
+        ws.apply(&[(TaskId::Number(8), remove_label.clone())])
+            .unwrap();
+        assert_eq!(root.read("inbox.md"), "- [x] &7 A\n- [ ] &8 B\n");
+        assert!(root.join(HISTORY_FNAME).exists());
+        // Back-date the files, so that any write shows in their mtimes.
+        let past = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        for path in ["inbox.md", HISTORY_FNAME] {
+            let file = fs::File::options()
+                .write(true)
+                .open(root.join(path))
+                .unwrap();
+            file.set_modified(past).unwrap();
+        }
+        let done = Change::Modify(Modification::SetStatus {
+            status: TaskStatus::Done,
+            with_subtasks: false,
+        });
+        let to_inbox = Change::Move(PathBuf::from("inbox.md"));
+        ws.apply(&[
+            (TaskId::Number(7), done),
+            (TaskId::Number(8), remove_label),
+            (TaskId::Number(8), to_inbox.clone()),
+        ])
+        .unwrap();
+        assert_eq!(mtime(&root, "inbox.md"), past);
+        assert_eq!(mtime(&root, HISTORY_FNAME), past);
+        // A real change still writes.
+        let add_label = Change::Modify(Modification::AddLabel("@y".to_string()));
+        ws.apply(&[(TaskId::Number(8), add_label)]).unwrap();
+        assert_eq!(root.read("inbox.md"), "- [x] &7 A\n- [ ] &8 B @y\n");
+        assert_ne!(mtime(&root, "inbox.md"), past);
+        assert_ne!(mtime(&root, HISTORY_FNAME), past);
+    }
+
+    #[test]
+    fn moves_tasks_with_subtasks_and_notes() {
+        let root = TempRoot::new(&[
+            (
+                "inbox.md",
+                "- [ ] &1 A\n  Note of A\n  - [ ] &2 B\n    Note of B\n- [ ] &3 C\n",
+            ),
+            ("work/today.md", "# Today\n- [ ] &4 D\n"),
+        ]);
+        let ws = Workspace::open(root.path()).unwrap();
+        let moved = ws
+            .apply(&[(
+                TaskId::Number(1),
+                Change::Move(PathBuf::from("work/today.md")),
+            )])
+            .unwrap();
+        assert_eq!(moved[0].path, root.join("work/today.md"));
+        assert_eq!(moved[0].task.line_number(), Some(3));
+        assert_eq!(moved[0].task.notes(), ["Note of A"]);
+        assert_eq!(root.read("inbox.md"), "- [ ] &3 C\n");
+        assert_eq!(
+            root.read("work/today.md"),
+            "# Today\n- [ ] &4 D\n- [ ] &1 A\n  Note of A\n  - [ ] &2 B\n    Note of B\n"
+        );
+        // A subtask moves out of its parent to the top level.
+        ws.apply(&[(TaskId::Number(2), Change::Move(PathBuf::from("inbox.md")))])
+            .unwrap();
+        assert_eq!(
+            root.read("inbox.md"),
+            "- [ ] &3 C\n- [ ] &2 B\n  Note of B\n"
+        );
+        assert_eq!(
+            root.read("work/today.md"),
+            "# Today\n- [ ] &4 D\n- [ ] &1 A\n  Note of A\n"
+        );
+    }
+
+    #[test]
+    fn moves_only_to_projects() {
+        let root = TempRoot::new(&[
+            ("inbox.md", "- [ ] &1 A\n- [ ] &2 B\n"),
+            ("work/today.md", ""),
+            (DONE_TASKS_FNAME, "- [x] &3 C\n"),
+        ]);
+        let ws = Workspace::open(root.path()).unwrap();
+        let outside = root.path().parent().unwrap().join("outside.md");
+        fs::write(&outside, "").unwrap();
+        for dest in [
+            PathBuf::from(DONE_TASKS_FNAME),
+            PathBuf::from("work/../.done"),
+            PathBuf::from("missing.md"),
+            PathBuf::from("work"),
+            PathBuf::from("../outside.md"),
+            outside.clone(),
+        ] {
+            let result = ws.apply(&[(TaskId::Number(1), Change::Move(dest.clone()))]);
+            assert!(
+                matches!(result, Err(Error::ProjectNotFound(_))),
+                "{}",
+                dest.display()
+            );
+        }
+        fs::remove_file(&outside).unwrap();
+        assert_eq!(root.read("inbox.md"), "- [ ] &1 A\n- [ ] &2 B\n");
+        assert_eq!(root.read(DONE_TASKS_FNAME), "- [x] &3 C\n");
+        // Moving to the file the task is in changes nothing.
+        let moved = ws
+            .apply(&[(
+                TaskId::Number(1),
+                Change::Move(PathBuf::from("work/../inbox.md")),
+            )])
+            .unwrap();
+        assert_eq!(moved[0].path, root.join("inbox.md"));
+        assert_eq!(root.read("inbox.md"), "- [ ] &1 A\n- [ ] &2 B\n");
+        assert!(!root.join(HISTORY_FNAME).exists());
+    }
+
+    /// The changed task lines, sorted, the files are in no particular order.
+    fn rewritten(changed: &[Project]) -> Vec<String> {
+        let mut lines: Vec<String> = changed
+            .iter()
+            .flat_map(|p| p.tasks.iter())
+            .map(|t| t.line().to_string())
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn rewrites_labels_everywhere_but_the_archive() {
+        let files = [
+            ("inbox.md", "- [ ] &1 A @a\n- [ ] &2 B @a @b\n"),
+            ("work/x.md", "- [ ] &3 C @b\n  - [ ] &4 D @a\n"),
+            (DONE_TASKS_FNAME, "- [x] &5 E @a\n"),
+        ];
+        let root = TempRoot::new(&files);
+        let ws = Workspace::open(root.path()).unwrap();
+        // A dry run only tells what would change.
+        let changed = ws.rewrite_label("@a", Some("@b"), true).unwrap();
+        assert_eq!(
+            rewritten(&changed),
+            ["  - [ ] &4 D @b", "- [ ] &1 A @b", "- [ ] &2 B @b"]
+        );
+        for (path, content) in files {
+            assert_eq!(root.read(path), content);
+        }
+        assert!(!root.join(HISTORY_FNAME).exists());
+
+        ws.rewrite_label("@a", Some("@b"), false).unwrap();
+        assert_eq!(root.read("inbox.md"), "- [ ] &1 A @b\n- [ ] &2 B @b\n");
+        assert_eq!(root.read("work/x.md"), "- [ ] &3 C @b\n  - [ ] &4 D @b\n");
+        assert_eq!(root.read(DONE_TASKS_FNAME), "- [x] &5 E @a\n");
+        assert!(ws.history().unwrap().changed(&TaskId::Number(2)).is_some());
+        assert!(ws
+            .rewrite_label("@a", Some("@b"), false)
+            .unwrap()
+            .is_empty());
+
+        let changed = ws.rewrite_label("@b", None, false).unwrap();
+        assert_eq!(rewritten(&changed).len(), 4);
+        assert_eq!(root.read("inbox.md"), "- [ ] &1 A\n- [ ] &2 B\n");
+        assert_eq!(root.read("work/x.md"), "- [ ] &3 C\n  - [ ] &4 D\n");
+        assert_eq!(root.read(DONE_TASKS_FNAME), "- [x] &5 E @a\n");
+    }
+}
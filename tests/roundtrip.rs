@@ -0,0 +1,93 @@
+use proptest::prelude::*;
+use rtd::{parse_task, Document, TaskStatus};
+
+/// Lines that look like what people keep in their notes: tasks with all kinds
+/// of tokens, odd spacing, headers and plain text.
+fn line() -> impl Strategy<Value = String> {
+    let token = prop_oneof![
+        "[a-zA-Z]{1,8}",
+        "&-?[0-9]{1,3}",
+        "&[a-z]{1,3}",
+        "%20[0-9]{2}-[01][0-9]-[0-3][0-9]",
+        "%20[0-9]{2}-[01][0-9]-[0-3][0-9]T[0-2][0-9]:[0-5][0-9]",
+        "\\^20[0-9]{2}-[01][0-9]-[0-3][0-9]",
+        "%[a-z]{1,5}",
+        "@[a-z]{0,5}",
+        "![0-5]",
+        "\\([A-E]\\)",
+        "\\*(every|after):[0-9a-z]{1,7}",
+        "https://[a-z]{1,5}\\.org",
+        "[^\\s]{1,4}",
+    ];
+    let separator = prop_oneof![" ", "  ", "\t", " \t "];
+    let task = (
+        prop_oneof!["- \\[[ xX/>-]\\]", "[ \t]{1,4}- \\[[ x]\\]"],
+        prop::collection::vec((separator, token), 0..8),
+        prop_oneof!["", " ", "  \t"],
+    )
+        .prop_map(|(status, tokens, trailing)| {
+            let mut line = status;
+            for (sep, token) in tokens {
+                line.push_str(&sep);
+                line.push_str(&token);
+            }
+            line + &trailing
+        });
+    prop_oneof![task, "[^\\n]{0,30}", "# [a-z ]{0,10}", Just(String::new())]
+}
+
+fn file() -> impl Strategy<Value = String> {
+    (
+        prop::collection::vec((line(), prop_oneof!["\n", "\r\n"]), 0..12),
+        any::<bool>(),
+    )
+        .prop_map(|(lines, trailing_newline)| {
+            let mut content: String = lines.into_iter().map(|(l, e)| l + &e).collect();
+            if !trailing_newline {
+                while content.ends_with('\n') || content.ends_with('\r') {
+                    content.pop();
+                }
+            }
+            content
+        })
+}
+
+proptest! {
+    #[test]
+    fn untouched_file_is_rendered_back_as_is(content in file()) {
+        prop_assert_eq!(Document::parse(&content).render(), content);
+    }
+
+    #[test]
+    fn untouched_task_is_rendered_back_as_is(line in line()) {
+        if let Some(task) = parse_task(&line) {
+            prop_assert_eq!(task.to_string(), line);
+        }
+    }
+
+    #[test]
+    fn edits_only_touch_changed_tokens(line in line()) {
+        if let Some(mut task) = parse_task(&line) {
+            // Only the checkbox character changes.
+            let checkbox = line.find('[').unwrap() + 1;
+            let status = task.status();
+            for other in TaskStatus::ALL {
+                task.set_status(other);
+                prop_assert_eq!(&task.line()[..checkbox], &line[..checkbox]);
+                prop_assert_eq!(&task.line()[checkbox + 1..], &line[checkbox + 1..]);
+                prop_assert_eq!(task.status(), other);
+            }
+            // `X` is written back as `x`, every other checkbox as it was.
+            if !line[checkbox..].starts_with('X') {
+                task.set_status(status);
+                prop_assert_eq!(task.line(), line.as_str());
+            }
+
+            let original = parse_task(&line).unwrap();
+            let mut labelled = original.clone();
+            labelled.add_label("@new");
+            prop_assert!(labelled.line().starts_with(line.trim_end()));
+            prop_assert_eq!(labelled.title(), original.title());
+        }
+    }
+}